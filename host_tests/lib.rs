@@ -0,0 +1,129 @@
+//! Host build of kernel modules for testing.
+//!
+//! The kernel itself is a no_std staticlib that needs the
+//! course support code to build, so its tests cannot run.
+//! Modules that only depend on each other are instead built
+//! here from the kernel sources, with the rest of the kernel
+//! replaced by the stand-ins below, and tested with cargo test.
+
+#![allow(warnings)]
+
+#[macro_use]
+#[path = "../variable_queue.rs"]
+mod variable_queue;
+
+#[macro_export]
+macro_rules! lprintf {
+    ($($arg:tt)*) => {()}
+}
+
+#[path = "../sync"]
+mod sync {
+    pub mod owned_lock;
+
+    pub mod disable_interrupts {
+        /// Interrupts are never taken on the host.
+        pub struct DisabledInterruptsGuard;
+
+        pub fn disableInterrupts() -> DisabledInterruptsGuard {
+            DisabledInterruptsGuard
+        }
+    }
+}
+
+#[path = "../thread"]
+mod thread {
+    mod policy;
+
+    use core::cell::Cell;
+    use core::ops::Deref;
+    use core::ptr::{self, NonNull};
+    use core::sync::atomic::AtomicPtr;
+    use std::thread_local;
+
+    use crate::sync::disable_interrupts::DisabledInterruptsGuard;
+    use crate::variable_queue::{Head, Link};
+
+    thread_local! {
+        /// The thread block standing in for the running thread.
+        static currentThread: Cell<*const ThreadBlock> = const { Cell::new(ptr::null()) };
+    }
+
+    /// Pretend that thread is running, as a context switch would.
+    pub fn setCurrentThread(thread: Option<&ThreadBlock>) {
+        currentThread.set(thread.map_or(ptr::null(), ptr::from_ref));
+    }
+
+    pub fn getCurrentThread<'a>() -> Option<&'a ThreadBlock> {
+        unsafe { currentThread.get().as_ref() }
+    }
+
+    /// Nothing else can run on the host, so a thread
+    /// that would have to wait never gets what it waits for.
+    pub fn yieldThread(thread: Option<&ThreadHandle>) -> Result<(), ()> {
+        panic!("yielded waiting for another thread");
+    }
+
+    pub fn yieldThreadWithoutInterrupts(_: &DisabledInterruptsGuard, thread: Option<&ThreadHandle>)
+                                       -> Result<(), ()> {
+        yieldThread(thread)
+    }
+
+    pub fn donatePriority(owner: &AtomicPtr<ThreadBlock>) {}
+
+    pub mod scheduler {
+        pub const NUM_PRIORITIES: usize = 32;
+    }
+
+    /// The parts of a thread block used by the modules under test.
+    pub struct ThreadBlock {
+        pub(crate) scheduleLink: Link<ThreadBlock>,
+        pub(crate) priority: Cell<u8>,
+        pub(crate) schedLevel: Cell<u8>,
+        pub(crate) inheritedPriority: Cell<u8>,
+        pub(crate) pass: Cell<u64>
+    }
+
+    impl ThreadBlock {
+        pub fn new() -> ThreadBlock {
+            ThreadBlock {
+                scheduleLink: Link::new(),
+                priority: Cell::new(16),
+                schedLevel: Cell::new(16),
+                inheritedPriority: Cell::new(u8::MAX),
+                pass: Cell::new(0)
+            }
+        }
+
+        pub fn effectivePriority(&self) -> u8 {
+            self.priority.get().min(self.inheritedPriority.get())
+        }
+
+        pub fn handle(&self) -> ThreadHandle {
+            ThreadHandle(NonNull::from_ref(self))
+        }
+
+        pub fn setBlockedOn(&self, owner: *const AtomicPtr<ThreadBlock>) {}
+
+        pub fn lockReleased(&self) {}
+
+        pub fn recomputeInheritedPriority(&self) {}
+    }
+
+    /// A thread handle without reference counting.
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct ThreadHandle(NonNull<ThreadBlock>);
+
+    impl Deref for ThreadHandle {
+        type Target = ThreadBlock;
+
+        fn deref(&self) -> &ThreadBlock {
+            unsafe { self.0.as_ref() }
+        }
+    }
+
+    pub type ThreadQueue = Head<ThreadBlock>;
+}
+
+#[cfg(test)]
+mod owned_lock_tests;
@@ -0,0 +1,61 @@
+//! Tests of OwnedLock, which tryLock alone can exercise
+//! as long as nothing has to wait.
+
+use crate::sync::owned_lock::OwnedLock;
+use crate::thread::{ThreadBlock, setCurrentThread};
+
+#[test]
+fn relock_after_unlock() {
+    let thread = ThreadBlock::new();
+    setCurrentThread(Some(&thread));
+
+    let lock = OwnedLock::new(0);
+
+    for i in 1..=3 {
+        let mut guard = lock.tryLock().expect("lock not released by its last guard");
+        *guard += 1;
+        assert_eq!(*guard, i);
+        drop(guard);
+    }
+
+    // Waiting succeeds at once too, without yielding.
+    drop(lock.waitForLock());
+}
+
+#[test]
+fn second_guard_is_refused() {
+    let thread = ThreadBlock::new();
+    setCurrentThread(Some(&thread));
+
+    let lock = OwnedLock::new(());
+    let guard = lock.tryLock().unwrap();
+
+    assert!(lock.tryLock().is_err());
+    drop(guard);
+    assert!(lock.tryLock().is_ok());
+}
+
+#[test]
+fn handoff() {
+    let holder = ThreadBlock::new();
+    let receiver = ThreadBlock::new();
+    let lock = OwnedLock::new(0);
+
+    setCurrentThread(Some(&holder));
+    let mut guard = lock.tryLock().unwrap();
+    *guard = 1;
+    guard.transferLockTo(&receiver);
+
+    // The lock stays locked for anyone but the receiver.
+    assert!(lock.tryLock().is_err());
+
+    setCurrentThread(Some(&receiver));
+    let mut guard = lock.tryLock().expect("transferred lock not taken by its receiver");
+    assert_eq!(*guard, 1);
+    *guard = 2;
+    drop(guard);
+
+    // And once the receiver unlocks, anyone may take it.
+    setCurrentThread(Some(&holder));
+    assert_eq!(*lock.tryLock().unwrap(), 2);
+}
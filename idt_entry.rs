@@ -26,3 +26,19 @@ pub const fn GATE(word1: u32, word2: u32) -> u64 {
 
 /// IDT Gate.
 pub type Gate = u64;
+
+/// Flags for a present 32-bit interrupt gate.
+pub const INTERRUPT_GATE_FLAGS: u32 = 0x8E00;
+
+/// Position of the privilege level in the upper word of a gate.
+pub const GATE_PRIVILEGE_START: u8 = 13;
+
+/// Construct an interrupt gate.
+///
+/// Interrupt gates disable interrupts on entry to the handler,
+/// so handlers start out unable to be preempted.
+#[inline(always)]
+pub const fn INTERRUPT_GATE(handler: u32, segment: u16, privilege: u8) -> Gate {
+    GATE(((segment as u32) << 16) | (handler & 0xFFFF),
+         (handler & 0xFFFF0000) | INTERRUPT_GATE_FLAGS | ((privilege as u32) << GATE_PRIVILEGE_START))
+}
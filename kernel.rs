@@ -16,6 +16,7 @@ mod variable_queue;
 mod sync;
 mod thread;
 mod registers;
+mod idt_entry;
 mod virtual_memory;
 mod byte_utils;
 mod malloc_wrappers;
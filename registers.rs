@@ -46,3 +46,25 @@ pub struct ExceptionState {
     pub esp: u32,
     pub ss: u32
 }
+
+/// Register state passed to user exception handlers
+///
+/// Matches the layout of ureg_t in the user library headers.
+/// reg.esp is always zero; the stack pointer is in esp.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct Ureg {
+    pub cause: u32,
+    pub cr2: u32,
+    pub ds: u32,
+    pub es: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub reg: Registers,
+    pub error_code: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    pub esp: u32,
+    pub ss: u32
+}
@@ -3,3 +3,8 @@ pub mod mutex;
 pub mod cond;
 pub mod disable_interrupts;
 pub mod rwlock;
+pub mod semaphore;
+pub mod irq_spinlock;
+pub mod once;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
@@ -17,7 +17,11 @@ use core::ptr::NonNull;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::sync::disable_interrupts::disableInterrupts;
-use crate::thread::{ThreadBlock, ThreadHandle, blockUntil, getCurrentThread, scheduleThread};
+use crate::thread::{
+    Interrupted, ThreadBlock, ThreadHandle, TimerNode,
+    addTimer, blockUntil, blockUntilUninterruptible, cancelTimer, deadlineAfter,
+    get_ticks, getCurrentThread, scheduleThread
+};
 use crate::lprintf;
 use crate::variable_queue::*;
 
@@ -59,6 +63,8 @@ pub struct QueueNode {
     doNotDeschedule: AtomicBool
 }
 
+unsafe impl Send for QueueNode {}
+
 pub type CondQueue = Head<QueueNode>;
 
 
@@ -75,6 +81,7 @@ pub struct Cond {
 
 impl Cond {
     /// Create a cond var.
+    #[track_caller]
     pub const fn new() -> Cond {
         Cond {
             queue: Mutex::new(Head::new())
@@ -121,12 +128,61 @@ impl Cond {
     /// not deschedule itself or stop descheduling itself,
     /// depending on when the signal was sent compared
     /// to when the queue was unlocked.
-    pub fn waitForCond<T>(&self, guard: MutexGuard<T>) -> MutexGuard<T> {
+    ///
+    /// If the calling thread is sent a signal first,
+    /// it takes itself off the queue and returns Err
+    /// without relocking the mutex.
+    /// If a cond signal arrives at the same time,
+    /// it was meant for us, so we take it rather than
+    /// leaving it lost, and the thread's signal is consumed.
+    pub fn waitForCond<'a, T>(&self, guard: MutexGuard<'a, T>) -> Result<MutexGuard<'a, T>, Interrupted> {
+        self.wait(guard, None, true).map(|(guard, _)| guard)
+    }
+
+    /// Wait until our cond var been signaled, ignoring signals
+    /// sent to the calling thread.
+    ///
+    /// Only for waits on kernel code that will signal us
+    /// in bounded time, as waitForCond is otherwise preferred.
+    ///
+    /// Not part of the original C implementation.
+    pub(super) fn waitForCondUninterruptible<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        match self.wait(guard, None, false) {
+            Ok((guard, _)) => guard,
+            Err(Interrupted) => unreachable!("uninterruptible wait interrupted")
+        }
+    }
+
+    /// Wait until our cond var been signaled,
+    /// or until ticks timer ticks have passed.
+    ///
+    /// Behaves as waitForCond, but also places a timer
+    /// on the queue whose expiry sets our doNotDeschedule flag,
+    /// waking us the same way a signal would.
+    /// If the timer fired, we take our node off the queue
+    /// ourselves; if it is already gone, a signal raced
+    /// the timer and removed us, so the wait counts as signaled.
+    ///
+    /// Returns the relocked guard and whether the wait timed out.
+    ///
+    /// Not part of the original C implementation.
+    pub fn wait_timeout<'a, T>(&self, guard: MutexGuard<'a, T>, ticks: u32)
+                              -> Result<(MutexGuard<'a, T>, bool), Interrupted> {
+        self.wait(guard, Some(ticks), true)
+    }
+
+    /// Wait until our cond var been signaled, a timeout
+    /// passes, or the thread is sent a signal.
+    ///
+    /// Implements waitForCond, waitForCondUninterruptible
+    /// and wait_timeout, as described there.
+    fn wait<'a, T>(&self, guard: MutexGuard<'a, T>, ticks: Option<u32>, interruptible: bool)
+                  -> Result<(MutexGuard<'a, T>, bool), Interrupted> {
         // Initialize the Queue information
         let thisThreadWaitInfo = QueueNode {
             doNotDeschedule: AtomicBool::new(TRY_TO_DESCHEDULE),
             link: Link::new(),
-            thread: getCurrentThread()
+            thread: getCurrentThread().map(|t| t.handle())
         };
 
         let thisThreadWaitInfo = pin!(thisThreadWaitInfo);
@@ -139,9 +195,13 @@ impl Cond {
         // Register yourself on the queue.
         let thisThreadWaitInfo = unsafe { insert_tail!(&mut queue, thisThreadWaitInfo.as_ref(), link) };
 
-        // Unlock the user mutex.
+        // Unlock the user mutex, then the queue,
+        // so that a signaler can reach us.
         let mutex = guard.mutex();
         drop(guard);
+        drop(queue);
+
+        let timer = pin!(ticks.map(|ticks| TimerNode::new(deadlineAfter(ticks), &thisThreadWaitInfo.doNotDeschedule)));
 
         // To give the appearance of atomicity, we will deschedule
         // the current thread only if the signal about
@@ -160,15 +220,52 @@ impl Cond {
         // problem of unlocking before switching out.
         // Thus, we rely on descheduleThread disabling
         // interrupts in this instance until context switch.
+        let blockedSince = get_ticks();
         let disabledInterrupts = disableInterrupts();
-        blockUntil(&disabledInterrupts, &thisThreadWaitInfo.doNotDeschedule);
 
-        // At this point, we've been rescheduled because
-        // a signal was sent. The signaler
+        if let Some(timer) = timer.as_ref().as_pin_ref() {
+            addTimer(&disabledInterrupts, timer);
+        }
+
+        let interrupted = if interruptible {
+            blockUntil(&disabledInterrupts, &thisThreadWaitInfo.doNotDeschedule).is_err()
+        } else {
+            blockUntilUninterruptible(&disabledInterrupts, &thisThreadWaitInfo.doNotDeschedule);
+            false
+        };
+
+        let expired = match &*timer {
+            Some(timer) => cancelTimer(&disabledInterrupts, timer),
+            None => false
+        };
+        drop(disabledInterrupts);
+
+        if let Some(thread) = getCurrentThread() {
+            thread.chargeBlocked(blockedSince);
+        }
+
+        // Unless we were woken by a signal, the signaler
         // was responsible for removing us from the queue,
         // so we can proceed.
+        // Otherwise, if we are still queued, no one signaled us,
+        // and we must take ourselves off the queue.
+        let mut timedOut = false;
+
+        if interrupted || expired {
+            let mut queue = self.queue.lock();
+
+            if thisThreadWaitInfo.link.in_queue() {
+                remove!(&mut queue, thisThreadWaitInfo, link);
+
+                if interrupted {
+                    return Err(Interrupted);
+                }
 
-        mutex.lock()
+                timedOut = true;
+            }
+        }
+
+        Ok((mutex.lock(), timedOut))
     }
 
     /// Signal and remove the given node from the queue.
@@ -203,10 +300,14 @@ impl Cond {
         /// and sending the signal means it won't deschedule,
         /// but with the signal sent it's fine for it to proceed.
         /// Our call to make_runnable will simply do nothing.
+        /// Once the signal is sent, the listener may return
+        /// and free its node, so we take its thread first.
+        let thread = node.thread.clone();
         send_signal(&node.doNotDeschedule);
 
-        let thread = node.thread?;
-        scheduleThread(disableInterrupts(), thread);
+        if let Some(thread) = thread {
+            let _ = scheduleThread(&disableInterrupts(), &thread);
+        }
     }
 
     /// Allow some thread on the queue to run.
@@ -218,21 +319,76 @@ impl Cond {
     /// Wraps the critical section of signalAndRemoveQueueNode
     /// with locks.
     pub fn signalCond(&self) {
-        let queue = self.queue.lock();
-
-        if let Some(front) = queue.front() {
-            Cond::signalAndRemoveQueueNode(queue, front);
-        }
+        self.notify_n(1);
     }
 
     /// Allow all threads on the queue to run.
     ///
     /// Locks the queue and sends everyone a signal.
     pub fn broadcastCond(&self) {
-        let queue = self.queue.lock();
+        self.notify_n(usize::MAX);
+    }
+
+    /// Allow up to count threads on the queue to run.
+    ///
+    /// Listeners are signaled in the order they started waiting.
+    /// Returns the number of listeners signaled.
+    ///
+    /// Not part of the original C implementation.
+    pub fn notify_n(&self, count: usize) -> usize {
+        let mut queue = self.queue.lock();
+        let mut signaled = 0;
+
+        while signaled < count && let Some(front) = queue.front_ptr() {
+            Cond::signalAndRemoveQueueNode(&mut queue, unsafe { &*front });
+            signaled += 1;
+        }
+
+        signaled
+    }
 
-        for curr in queue.iter(|n| n.link) {
-            Cond::signalAndRemoveQueueNode(queue, curr);
+    /// Wait for as long as condition holds for the protected data.
+    ///
+    /// The condition is checked before waiting and after
+    /// every wakeup, with the mutex locked, so neither
+    /// a signal sent before we wait nor a spurious wakeup
+    /// lets us return while it still holds.
+    ///
+    /// As with waitForCond, returns Err without the mutex
+    /// locked if the thread is sent a signal.
+    ///
+    /// Not part of the original C implementation.
+    pub fn wait_while<'a, T, F>(&self, guard: MutexGuard<'a, T>, condition: F)
+                               -> Result<MutexGuard<'a, T>, Interrupted>
+    where F: FnMut(&mut T) -> bool {
+        self.waitWhile(guard, true, condition)
+    }
+
+    /// Wait for as long as condition holds for the protected data,
+    /// ignoring signals sent to the calling thread unless interruptible.
+    ///
+    /// Lets kernel code share a wait path between its
+    /// interruptible and uninterruptible operations.
+    ///
+    /// Not part of the original C implementation.
+    pub(super) fn waitWhile<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, interruptible: bool, mut condition: F)
+                                     -> Result<MutexGuard<'a, T>, Interrupted>
+    where F: FnMut(&mut T) -> bool {
+        while condition(&mut *guard) {
+            guard = self.wait(guard, None, interruptible)?.0;
         }
+
+        Ok(guard)
+    }
+
+    /// Wait until condition holds for the protected data.
+    ///
+    /// As wait_while, with the condition inverted.
+    ///
+    /// Not part of the original C implementation.
+    pub fn wait_until<'a, T, F>(&self, guard: MutexGuard<'a, T>, mut condition: F)
+                               -> Result<MutexGuard<'a, T>, Interrupted>
+    where F: FnMut(&mut T) -> bool {
+        self.wait_while(guard, |data| !condition(data))
     }
 }
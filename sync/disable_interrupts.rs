@@ -3,47 +3,95 @@
 //! While the concept of disabling interrupts
 //! was central to the original C implementation,
 //! this file is new to the Rust port.
+//!
+//! The outermost guard on a thread records whether
+//! interrupts were enabled before disabling them,
+//! and restores that state once it is dropped.
+//! The record is kept in the thread block, so a thread
+//! switched away from while holding a guard gets
+//! its own state back when it drops the guard later,
+//! whatever the threads in between did.
+
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use _410kern::eflags::EFL_IF;
 
 use crate::thread::getCurrentThread;
 
-static mut noThreadRefCount: u32 = 0;
+/// Number of guards created while there was no current thread.
+///
+/// Like the per-thread record, this is only touched
+/// with interrupts disabled, so relaxed accesses suffice.
+static noThreadRefCount: AtomicU32 = AtomicU32::new(0);
+
+/// Whether interrupts were enabled before the outermost guard
+/// created while there was no current thread.
+static noThreadInterruptsWereEnabled: AtomicBool = AtomicBool::new(false);
+
+/// Whether interrupts are currently enabled.
+#[inline(always)]
+fn interruptsEnabled() -> bool {
+    let eflags: u32;
+    unsafe { asm!("pushf", "pop {}", out(reg) eflags, options(att_syntax)); }
+    eflags & EFL_IF != 0
+}
 
 /// Calling this function while interrupts are already disabled through a separate
 /// mechanism makes it undefined whether interrupts are currently disabled or not.
 /// Users of DisabledInterruptsGuard must not not rely soley on this for
 /// memory safety.
 pub fn disableInterrupts() -> DisabledInterruptsGuard {
+    let wereEnabled = interruptsEnabled();
+
+    // Acts as a compiler barrier, so that nothing
+    // protected by the guard is moved before it.
+    unsafe { asm!("cli", options(att_syntax, nostack)); }
+
     match getCurrentThread() {
-        None => unsafe {
-            noThreadRefCount += 1;
+        None => {
+            if noThreadRefCount.load(Ordering::Relaxed) == 0 {
+                noThreadInterruptsWereEnabled.store(wereEnabled, Ordering::Relaxed);
+            }
+
+            noThreadRefCount.fetch_add(1, Ordering::Relaxed);
         }
         Some(thread) => {
+            if thread.disabledInterruptsRefCount.get() == 0 {
+                thread.interruptsWereEnabled.set(wereEnabled);
+            }
+
             thread.disabledInterruptsRefCount.update(|i| i + 1);
         }
     }
 
-    DisabledInterruptsGuard()
+    DisabledInterruptsGuard(PhantomData)
 }
 
+/// Proof that interrupts are disabled.
+///
+/// Interrupt state belongs to the thread that disabled them,
+/// so the guard cannot be sent to another thread.
 #[derive(Debug)]
-pub struct DisabledInterruptsGuard();
+pub struct DisabledInterruptsGuard(PhantomData<*mut ()>);
 
 impl Drop for DisabledInterruptsGuard {
     fn drop(&mut self) {
-        let refCount = match getCurrentThread() {
-            None => unsafe {
-                noThreadRefCount -= 1;
-                noThreadRefCount
-            },
+        let (refCount, wereEnabled) = match getCurrentThread() {
+            None => (
+                noThreadRefCount.fetch_sub(1, Ordering::Relaxed) - 1,
+                noThreadInterruptsWereEnabled.load(Ordering::Relaxed)
+            ),
             Some(thread) => {
                 let i = thread.disabledInterruptsRefCount.get() - 1;
                 thread.disabledInterruptsRefCount.set(i);
-                i
+                (i, thread.interruptsWereEnabled.get())
             }
         };
 
-        if refCount == 0 {
-
+        if refCount == 0 && wereEnabled {
+            unsafe { asm!("sti", options(att_syntax, nostack)); }
         }
     }
 }
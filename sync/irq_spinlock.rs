@@ -0,0 +1,132 @@
+//! A spinlock for data shared with interrupt handlers.
+//!
+//! Locking disables interrupts for as long as the lock is held,
+//! so on our single processor the lock can only be contended
+//! if its holder was switched away from while holding it.
+//! That is always a bug, so holders are counted per thread
+//! and checked at every context switch.
+//! Spinning is still expected if the holder was interrupted
+//! by a handler taking the same lock, so only a thread
+//! spinning on a lock it already holds is reported.
+//!
+//! Not part of the original C implementation, where the
+//! schedule and similar structures were protected by
+//! disabling interrupts alone.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::thread::{ThreadBlock, getCurrentThread};
+
+use super::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+
+/// A lock that keeps interrupts disabled while held.
+///
+/// Contains:
+///   locked: Whether the lock is held.
+///   owner: The thread holding the lock, if it has one,
+///          for catching a thread that locks it twice.
+///   data: The protected data.
+#[derive(Debug)]
+pub struct IrqSpinLock<T> {
+    locked: AtomicBool,
+    owner: AtomicPtr<ThreadBlock>,
+    data: UnsafeCell<T>
+}
+
+unsafe impl<T> Sync for IrqSpinLock<T> where T: Send {}
+
+impl<T> IrqSpinLock<T> {
+    /// Create an unlocked spinlock.
+    pub const fn new(data: T) -> IrqSpinLock<T> {
+        IrqSpinLock {
+            locked: AtomicBool::new(false),
+            owner: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(data)
+        }
+    }
+
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Disable interrupts and spin until we hold the lock.
+    ///
+    /// Interrupts stay disabled until the guard is dropped.
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let disabledInterrupts = disableInterrupts();
+        let thread = getCurrentThread();
+
+        while self.locked.swap(true, Ordering::Acquire) {
+            debug_assert!(
+                thread.is_none_or(|t| !ptr::eq(self.owner.load(Ordering::Relaxed), t)),
+                "IrqSpinLock locked twice by the same thread"
+            );
+            spin_loop();
+        }
+
+        self.acquired(thread);
+
+        IrqSpinLockGuard(self, disabledInterrupts)
+    }
+
+    /// Disable interrupts and take the lock if it is free.
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let disabledInterrupts = disableInterrupts();
+
+        if self.locked.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        self.acquired(getCurrentThread());
+
+        Some(IrqSpinLockGuard(self, disabledInterrupts))
+    }
+
+    /// Record that thread now holds the lock.
+    fn acquired(&self, thread: Option<&ThreadBlock>) {
+        if let Some(thread) = thread {
+            thread.spinLocksHeld.update(|n| n + 1);
+            self.owner.store(ptr::from_ref(thread).cast_mut(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Access to the data behind an IrqSpinLock.
+///
+/// Dropping the guard unlocks the lock, and then
+/// restores interrupts through the contained guard.
+#[derive(Debug)]
+pub struct IrqSpinLockGuard<'a, T>(&'a IrqSpinLock<T>, DisabledInterruptsGuard);
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    /// Unlock the spinlock.
+    ///
+    /// The interrupt guard is dropped after this returns,
+    /// so interrupts are only restored once we have unlocked.
+    fn drop(&mut self) {
+        if let Some(thread) = getCurrentThread() {
+            thread.spinLocksHeld.update(|n| n - 1);
+        }
+
+        self.0.owner.store(ptr::null_mut(), Ordering::Relaxed);
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
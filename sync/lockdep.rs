@@ -0,0 +1,282 @@
+//! Lock order validation.
+//!
+//! When the lockdep feature is enabled, every wait for a
+//! Mutex or OwnedLock is recorded against a lock class,
+//! keyed by the lock's type and the site that constructed it,
+//! so that every lock made in the same place shares a class.
+//! Each thread keeps a stack of the locks it holds, and acquiring
+//! a lock while holding another adds an edge to a global lock-order graph.
+//! An acquisition that would close a cycle in that graph,
+//! or that waits on a lock the thread already holds,
+//! is reported along with the call sites involved.
+//! Locks of the same class are not ordered against each other.
+//!
+//! Checks happen before waiting, so ordering mistakes
+//! are reported even on runs where they do not deadlock.
+//!
+//! Not part of the original C implementation.
+
+use core::any::type_name;
+use core::cell::Cell;
+use core::panic::Location;
+use core::ptr;
+
+use crate::lprintf;
+use crate::thread::getCurrentThread;
+
+use super::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+use super::irq_spinlock::{IrqSpinLock, IrqSpinLockGuard};
+
+/// Maximum number of lock classes that can be tracked.
+pub const MAX_LOCK_CLASSES: usize = 64;
+
+/// Maximum number of locks a thread can be tracked holding at once.
+///
+/// Kept small, as every thread block holds a stack of this size
+/// at the bottom of its kernel stack.
+pub const MAX_HELD_LOCKS: usize = 8;
+
+/// Where a lock was constructed or acquired.
+pub type Site = &'static Location<'static>;
+
+/// A lock held, or being waited on, by a thread.
+///
+/// addr tells apart locks of the same class.
+#[derive(Debug, Copy, Clone)]
+struct HeldLock {
+    class: u8,
+    addr: usize,
+    site: Site
+}
+
+/// The locks held by a thread, in acquisition order.
+#[derive(Debug)]
+pub struct HeldLocks {
+    locks: [Cell<Option<HeldLock>>; MAX_HELD_LOCKS],
+    depth: Cell<u8>
+}
+
+/// A class of locks being tracked.
+///
+/// Keyed by type as well as construction site,
+/// as a mutex constructs its own locks at the same site.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct LockClass {
+    site: Site,
+    name: &'static str
+}
+
+/// The lock classes seen so far and the order
+/// they have been acquired in.
+///
+/// Contains:
+///   classes: Each registered class, None for free slots.
+///            Classes are never unregistered.
+///   after: Bitset per class of classes acquired while holding it.
+///   edgeSites: Where each edge in after was first seen.
+#[derive(Debug)]
+struct LockGraph {
+    classes: [Option<LockClass>; MAX_LOCK_CLASSES],
+    after: [u64; MAX_LOCK_CLASSES],
+    edgeSites: [[Option<Site>; MAX_LOCK_CLASSES]; MAX_LOCK_CLASSES]
+}
+
+/// The lock-order graph.
+///
+/// Like the schedule, protected by an IrqSpinLock,
+/// which is never instrumented.
+static graph: IrqSpinLock<LockGraph> = IrqSpinLock::new(LockGraph::new());
+
+
+impl HeldLocks {
+    /// Create an empty held lock stack.
+    pub const fn new() -> HeldLocks {
+        HeldLocks {
+            locks: [const { Cell::new(None) }; MAX_HELD_LOCKS],
+            depth: Cell::new(0)
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item=HeldLock> + '_ {
+        self.locks[..self.depth.get() as usize].iter().filter_map(|l| l.get())
+    }
+
+    fn push(&self, lock: HeldLock) {
+        let depth = self.depth.get();
+
+        match self.locks.get(depth as usize) {
+            Some(slot) => {
+                slot.set(Some(lock));
+                self.depth.set(depth + 1);
+            },
+            None => panic!("lockdep: more than {} locks held, acquiring the lock at {}",
+                MAX_HELD_LOCKS, lock.site)
+        }
+    }
+
+    /// Remove the most recent entry for a lock.
+    ///
+    /// Locks may be released in any order.
+    fn remove(&self, class: u8, addr: usize) {
+        let depth = self.depth.get() as usize;
+
+        let Some(index) = (0..depth).rev().find(|&i| {
+            self.locks[i].get().is_some_and(|l| l.class == class && l.addr == addr)
+        })
+        else { return; };
+
+        for i in index..depth - 1 {
+            self.locks[i].set(self.locks[i + 1].get());
+        }
+
+        self.locks[depth - 1].set(None);
+        self.depth.set(depth as u8 - 1);
+    }
+}
+
+impl LockGraph {
+    const fn new() -> LockGraph {
+        LockGraph {
+            classes: [None; MAX_LOCK_CLASSES],
+            after: [0; MAX_LOCK_CLASSES],
+            edgeSites: [[None; MAX_LOCK_CLASSES]; MAX_LOCK_CLASSES]
+        }
+    }
+
+    /// Find the index of a class, if it is registered.
+    fn findClass(&self, class: LockClass) -> Option<usize> {
+        self.classes.iter().position(|c| *c == Some(class))
+    }
+
+    /// Find the index of a class, registering it if it is new.
+    ///
+    /// Panics if there is no room for another class,
+    /// as lock order could no longer be checked.
+    fn classIndex(&mut self, class: LockClass) -> usize {
+        if let Some(index) = self.findClass(class) {
+            return index;
+        }
+
+        let Some(index) = self.classes.iter().position(Option::is_none)
+        else {
+            panic!("lockdep: more than {} lock classes, registering {} constructed at {}",
+                MAX_LOCK_CLASSES, class.name, class.site);
+        };
+
+        self.classes[index] = Some(class);
+        index
+    }
+
+    /// Name of a registered class, for reports.
+    fn name(&self, index: usize) -> &'static str {
+        self.classes[index].map_or("?", |c| c.name)
+    }
+
+    /// Whether to is acquired after from, directly or through other classes.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited = 0u64;
+        let mut frontier = 1u64 << from;
+
+        while frontier != 0 {
+            if frontier & (1 << to) != 0 {
+                return true;
+            }
+
+            visited |= frontier;
+
+            let mut next = 0;
+            for i in 0..MAX_LOCK_CLASSES {
+                if frontier & (1 << i) != 0 {
+                    next |= self.after[i];
+                }
+            }
+
+            frontier = next & !visited;
+        }
+
+        false
+    }
+}
+
+fn getGraph(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<LockGraph> {
+    graph.lock()
+}
+
+/// The class of a lock of type L constructed at constructed.
+fn lockClass<L>(constructed: Site) -> LockClass {
+    LockClass {
+        site: constructed,
+        name: type_name::<L>()
+    }
+}
+
+/// Record that the current thread is about to wait for a lock
+/// constructed at constructed.
+///
+/// Reports waiting on a lock the thread already holds,
+/// and acquisitions that invert an order seen before.
+pub fn acquire<L>(lock: &L, constructed: Site, site: Site) {
+    let Some(thread) = getCurrentThread()
+    else { return; };
+
+    let class = lockClass::<L>(constructed);
+    let addr = ptr::from_ref(lock).addr();
+
+    let disabledInterrupts = disableInterrupts();
+    let mut graph = getGraph(&disabledInterrupts);
+
+    let index = graph.classIndex(class);
+
+    for held in thread.heldLocks.iter() {
+        let heldIndex = held.class as usize;
+
+        if heldIndex == index {
+            if held.addr == addr {
+                lprintf!("lockdep: self-deadlock acquiring {} at {}, already held since {}\n",
+                    class.name, site, held.site);
+            }
+            continue;
+        }
+
+        if graph.reaches(index, heldIndex) {
+            lprintf!("lockdep: lock order inversion acquiring {} at {} while holding {} from {}; \
+                the reverse order was first seen at {:?}\n",
+                class.name, site, graph.name(heldIndex), held.site, graph.edgeSites[index][heldIndex]);
+        } else if graph.after[heldIndex] & (1 << index) == 0 {
+            graph.after[heldIndex] |= 1 << index;
+            graph.edgeSites[heldIndex][index] = Some(site);
+        }
+    }
+
+    drop(graph);
+    thread.heldLocks.push(HeldLock { class: index as u8, addr, site });
+}
+
+/// Record that the current thread has taken a lock
+/// constructed at constructed without waiting.
+///
+/// A try-lock cannot deadlock, so no order is checked,
+/// but later acquisitions are still ordered after it.
+pub fn acquired<L>(lock: &L, constructed: Site, site: Site) {
+    let Some(thread) = getCurrentThread()
+    else { return; };
+
+    let disabledInterrupts = disableInterrupts();
+    let index = getGraph(&disabledInterrupts).classIndex(lockClass::<L>(constructed));
+
+    thread.heldLocks.push(HeldLock { class: index as u8, addr: ptr::from_ref(lock).addr(), site });
+}
+
+/// Record that the current thread no longer holds
+/// or waits for a lock constructed at constructed.
+pub fn release<L>(lock: &L, constructed: Site) {
+    let Some(thread) = getCurrentThread()
+    else { return; };
+
+    let disabledInterrupts = disableInterrupts();
+    let index = getGraph(&disabledInterrupts).findClass(lockClass::<L>(constructed));
+
+    if let Some(index) = index {
+        thread.heldLocks.remove(index as u8, ptr::from_ref(lock).addr());
+    }
+}
@@ -2,13 +2,14 @@
 //!
 //! This implementation is primarly based around
 //! requesters for a mutex lock being added to a queue,
-//! and spin-waiting on a flag for whether they
-//! have the lock until they can continue.
+//! and blocking on a flag until the lock is
+//! handed to them.
 //! The queue itself uses xchg to guarantee
 //! atomicity.
 
 use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut};
+use core::panic::Location;
 use core::pin::pin;
 use core::ptr::{self, NonNull};
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -17,37 +18,61 @@ use crate::lprintf;
 use crate::variable_queue::*;
 use crate::thread::*;
 
+#[cfg(feature = "lockdep")]
+use super::lockdep;
+use super::cond::{DO_NOT_DESCHEDULE, TRY_TO_DESCHEDULE};
+use super::disable_interrupts::disableInterrupts;
 use super::owned_lock::{self, OwnedLock, OwnedLockGuard};
 
+/// A node in the mutex waitlist.
+///
+/// Contains:
+///   link: Queue link to use in variable queue macros.
+///   wake: Set to DO_NOT_DESCHEDULE once the lock is handed
+///         to the waiter, or its deadline passes.
+///   thread: The waiting thread.
 #[derive(Debug)]
 pub struct WaitListNode {
     link: Link<WaitListNode>,
-    hasLock: AtomicBool,
-    thread: Option<NonNull<ThreadBlock>>
+    wake: AtomicBool,
+    thread: Option<WeakThreadHandle>
 }
 
 pub type MutexWaitList = Head<WaitListNode>;
 
+/// Why a waiter gave up on a mutex.
+#[derive(Debug, Clone, Copy)]
+enum GaveUp {
+    TimedOut,
+    Interrupted
+}
+
 /// Mutex structure
 ///
 /// Contains:
 /// waitlistLock: a lock on the waitlist to acquire the mutex.
 /// mutexLock: The main lock to try to acquire.
 /// waitlist: the waitlist to acquire the mutex.
+/// constructed: Where the mutex was created, its lockdep class.
 #[derive(Debug)]
 pub struct Mutex<T> {
     waitList: OwnedLock<MutexWaitList>,
-    mutexLock: OwnedLock<T>
+    mutexLock: OwnedLock<T>,
+    #[cfg(feature = "lockdep")]
+    constructed: lockdep::Site
 }
 
 unsafe impl<T> Sync for Mutex<T> where T: Send {}
 
 impl<T> Mutex<T> {
     /// Create a mutex.
+    #[track_caller]
     pub const fn new(data: T) -> Self {
         Mutex {
             waitList: OwnedLock::new(Head::new()),
             mutexLock: OwnedLock::new(data),
+            #[cfg(feature = "lockdep")]
+            constructed: Location::caller()
         }
     }
 
@@ -78,6 +103,69 @@ impl<T> Drop for Mutex<T> {
 impl<T> Mutex<T> {
     /// Wait until the calling thread owns the mutex.
     ///
+    /// Signals sent to the calling thread are not acted on
+    /// until the lock is taken, as holders only keep a mutex
+    /// for a critical section; lock_interruptible is for
+    /// mutexes that may be held across a long wait.
+    ///
+    /// This lock is not re-entrant; if this thread already owns the lock
+    /// this function will deadlock.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self, self.constructed, Location::caller());
+
+        match self.acquire(None, false) {
+            Ok(guard) => guard,
+            Err(_) => unreachable!("uninterruptible mutex wait without a deadline gave up")
+        }
+    }
+
+    /// Wait until the calling thread owns the mutex,
+    /// or until ticks timer ticks have passed.
+    ///
+    /// Returns Err if the deadline passed first.
+    ///
+    /// Not part of the original C implementation.
+    #[track_caller]
+    pub fn lock_timeout(&self, ticks: u32) -> Result<MutexGuard<T>, TimedOut> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self, self.constructed, Location::caller());
+
+        let result = self.acquire(Some(deadlineAfter(ticks)), false);
+
+        #[cfg(feature = "lockdep")]
+        if result.is_err() {
+            lockdep::release(self, self.constructed);
+        }
+
+        result.map_err(|_| TimedOut)
+    }
+
+    /// Wait until the calling thread owns the mutex,
+    /// or until it is sent a signal.
+    ///
+    /// Returns Err if the signal arrived first.
+    ///
+    /// Not part of the original C implementation.
+    #[track_caller]
+    pub fn lock_interruptible(&self) -> Result<MutexGuard<T>, Interrupted> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self, self.constructed, Location::caller());
+
+        let result = self.acquire(None, true);
+
+        #[cfg(feature = "lockdep")]
+        if result.is_err() {
+            lockdep::release(self, self.constructed);
+        }
+
+        result.map_err(|_| Interrupted)
+    }
+
+    /// Wait until the calling thread owns the mutex,
+    /// giving up once deadline passes, if there is one.
+    ///
     /// Our implementation uses a queue called
     /// the waitlist to track requesters of the lock.
     /// Be cause everyone who is waiting
@@ -94,105 +182,133 @@ impl<T> Mutex<T> {
     /// ways. The first is that whoever previously
     /// held the lock and unlocked it may hand the lock off.
     /// This previous holder is then responsible
-    /// for removing the receiver off the waitlist
-    /// and signaling through the receiver's hasLock flag
-    /// that they may proceed. The second is that
-    /// if somehow the mutex is fully unlocked without
-    /// being handed off, a waiter may steal the lock.
-    /// This is possible if the previous holder
-    /// frees while a new wave of requesters
-    /// joins the waitlist.
+    /// for removing the receiver off the waitlist,
+    /// transferring the lock, and waking the receiver
+    /// through the same flag protocol that cond vars use.
+    /// The second is that the mutex was unlocked
+    /// while the waiter was registering, in which case
+    /// it takes the lock right away and leaves the waitlist.
     ///
-    /// We claim that a requesting thread cannot go through
-    /// more than 2 xchg races in a row without
-    /// being added to the waitlist, where it must then wait
-    /// its turn.
+    /// With a deadline, a timer on the same flag wakes the
+    /// waiter once it passes, and the waiter takes its node
+    /// off the waitlist with the waitlist locked.
+    /// If the node is already gone, an unlocker has chosen
+    /// us and is handing us the lock, so we take it
+    /// rather than leave the mutex locked with no holder.
+    /// An interruptible waiter gives up on a signal
+    /// the same way.
     ///
-    /// This lock is not re-entrant; if this thread already owns the lock
-    /// this function will deadlock.
-    pub fn lock(&self) -> MutexGuard<T> {
+    /// Not part of the original C implementation in this form;
+    /// waiters there spun yielding to the holder.
+    fn acquire(&self, deadline: Option<u32>, interruptible: bool) -> Result<MutexGuard<T>, GaveUp> {
         let thisThread = getCurrentThread();
 
-        // Initialize the Waiter information
-        let thisThreadWaitInfo = WaitListNode {
-            hasLock: AtomicBool::new(false),
-            link: Link::new(),
-            thread: thisThread
-        };
-
-        // Update Waitlist
-
         let mut waitList = self.waitList.waitForLock();
 
         // If the waitlist is empty and the mutex is unlocked,
         // no need to wait; you get the lock!
         if waitList.tail().is_none() && let Ok(guard) = self.mutexLock.tryLock() {
-            // Release access to the waitlist
             drop(waitList);
+            return Ok(MutexGuard(self, ManuallyDrop::new(guard)));
+        }
 
-            MutexGuard(self, ManuallyDrop::new(guard))
-        } else {
-            // Otherwise, you need to to register yourself on the waitlist
-            // and wait.
-
-            let thisThreadWaitInfo = pin!(thisThreadWaitInfo);
-
-            let thisThreadWaitInfo = unsafe {
-                // Register yourself on the waitlist.
-                insert_tail!(&mut waitList, thisThreadWaitInfo.as_ref(), link)
-            };
+        // Otherwise, you need to to register yourself on the waitlist
+        // and wait.
+        let thisThreadWaitInfo = pin!(WaitListNode {
+            link: Link::new(),
+            wake: AtomicBool::new(TRY_TO_DESCHEDULE),
+            thread: thisThread.map(|t| t.weakHandle())
+        });
 
-            // Release access to the waitlist
-            drop(waitList);
+        let thisThreadWaitInfo = unsafe {
+            insert_tail!(&mut waitList, thisThreadWaitInfo.as_ref(), link)
+        };
 
+        drop(waitList);
 
-            // Wait for lock.
+        let timer = pin!(deadline.map(|deadline| TimerNode::new(deadline, &thisThreadWaitInfo.wake)));
+        if let Some(timer) = timer.as_ref().as_pin_ref() {
+            addTimer(&disableInterrupts(), timer);
+        }
 
-            // If the lock was freed while you were registering,
-            // you have a chance to steal the lock.
-            let mut mutexResult = self.mutexLock.tryLock();
+        let blockedSince = get_ticks();
+        let mut interrupted = false;
 
-            // Wait to receive or steal the main lock
-            let guard = loop {
-                match mutexResult {
-                    Ok(guard) => break guard,
-                    Err(mutexHolder) => {
-                        yieldThread(mutexHolder);
+        let result = loop {
+            // Succeeds if the lock was freed while we registered,
+            // or once it has been handed to us.
+            if let Ok(guard) = self.mutexLock.tryLock() {
+                break Ok(guard);
+            }
 
-                        // Attempt to steal the lock.
-                        mutexResult = self.mutexLock.tryLock();
-                    }
-                }
+            let gaveUp = if interrupted {
+                Some(GaveUp::Interrupted)
+            } else if timer.as_ref().get_ref().as_ref().is_some_and(TimerNode::expired) {
+                Some(GaveUp::TimedOut)
+            } else {
+                None
             };
 
-            // Lock received, cleanup.
-
-            // If you stole the lock, you have to remove yourself
-            // from the waitlist.
-            if !thisThreadWaitInfo.hasLock.load(Ordering::Acquire) {
+            if let Some(reason) = gaveUp {
                 let mut waitList = self.waitList.waitForLock();
 
-                remove!(&mut waitList, thisThreadWaitInfo, link);
+                if thisThreadWaitInfo.link.in_queue() {
+                    remove!(&mut waitList, thisThreadWaitInfo, link);
+                    break Err(reason);
+                }
 
-                // Release access to the waitlist
+                // An unlocker has chosen us and is about
+                // to hand the lock over.
                 drop(waitList);
+                let _ = yieldThread(None);
+                continue;
             }
 
-            // Otherwise, whoever unlocked us was responsible for removing us
-            // from the waitlist, so we're ready to go!
+            // Keep the holder from being starved by
+            // less important threads while we wait.
+            self.mutexLock.donateToOwner();
+
+            let disabledInterrupts = disableInterrupts();
+            if interruptible {
+                interrupted = blockUntil(&disabledInterrupts, &thisThreadWaitInfo.wake).is_err();
+            } else {
+                blockUntilUninterruptible(&disabledInterrupts, &thisThreadWaitInfo.wake);
+            }
+        };
 
-            drop(thisThreadWaitInfo);
+        if let Some(timer) = timer.as_ref().get_ref() {
+            cancelTimer(&disableInterrupts(), timer);
+        }
 
-            MutexGuard(self, ManuallyDrop::new(guard))
+        if let Some(thread) = thisThread {
+            thread.setBlockedOn(ptr::null());
+            thread.chargeBlocked(blockedSince);
         }
+
+        let guard = result?;
+
+        // If the lock was not handed to us,
+        // we have to remove ourselves from the waitlist.
+        let mut waitList = self.waitList.waitForLock();
+        if thisThreadWaitInfo.link.in_queue() {
+            remove!(&mut waitList, thisThreadWaitInfo, link);
+        }
+        drop(waitList);
+
+        Ok(MutexGuard(self, ManuallyDrop::new(guard)))
     }
 
+    #[track_caller]
     pub fn tryLock(&self) -> Option<MutexGuard<T>> {
         if let Ok(waitList) = self.waitList.tryLock()
             && waitList.tail().is_none()
             && let Ok(guard) = self.mutexLock.tryLock()
         {
             drop(waitList);
+
+            #[cfg(feature = "lockdep")]
+            lockdep::acquired(self, self.constructed, Location::caller());
+
             Some(MutexGuard(self, ManuallyDrop::new(guard)))
         } else {
             None
@@ -229,11 +345,14 @@ impl<T> Drop for MutexGuard<'_, T> {
     /// If there is anyone in the waitlist, the unlocker's job
     /// is to choose the first waiter and hand them the lock
     /// immediately, both removing them from the
-    /// waitlist and signaling through hasLock.
+    /// waitlist and waking them through their wake flag.
     ///
     /// If no one is on the waitlist, the mutex status is set to
     /// unlocked so the next requester can take it.
     fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.0, self.0.constructed);
+
         let mut waitList = self.0.waitList.waitForLock();
 
         // If the waitlist is empty, we indicate the mutex is now unlocked
@@ -261,8 +380,21 @@ impl<T> Drop for MutexGuard<'_, T> {
                 drop(waitList);
 
                 // Triggers to the next runner they are ready to go.
-                unsafe { ManuallyDrop::take(&mut self.1).transferLockTo(nextRunner.thread.unwrap()) };
-                nextRunner.hasLock.store(true, Ordering::Release);
+                // A stale handle means the runner's thread is gone,
+                // in which case the lock is simply released.
+                let guard = unsafe { ManuallyDrop::take(&mut self.1) };
+                match nextRunner.thread.as_ref().and_then(|t| t.upgrade()) {
+                    Some(thread) => {
+                        // Once it owns the lock, the runner may return
+                        // and free its node, so it must not run until
+                        // we are done with the node.
+                        let disabledInterrupts = disableInterrupts();
+                        guard.transferLockTo(&thread);
+                        nextRunner.wake.store(DO_NOT_DESCHEDULE, Ordering::Release);
+                        let _ = scheduleThread(&disabledInterrupts, &thread);
+                    },
+                    None => drop(guard)
+                }
             }
         }
     }
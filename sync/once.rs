@@ -0,0 +1,213 @@
+//! One-time initialization of kernel statics.
+//!
+//! The first caller to get at a Once runs its initializer;
+//! anyone who arrives while that is still in progress
+//! sleeps on a cond var until the value is ready,
+//! rather than spinning on the flag.
+//! Initializers that found nobody waiting skip the
+//! mutex altogether, so a Once can be initialized during
+//! boot, before there are threads to wait with.
+//!
+//! Not part of the original C implementation, which
+//! initialized its globals by hand from kernel_main.
+
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::cond::Cond;
+use super::mutex::Mutex;
+
+/// No initializer has run yet.
+const INCOMPLETE: u8 = 0;
+
+/// An initializer is running and nobody is waiting for it.
+const RUNNING: u8 = 1;
+
+/// An initializer is running and must wake its waiters.
+const WAITED: u8 = 2;
+
+/// The value is initialized.
+const COMPLETE: u8 = 3;
+
+/// A value that is initialized at most once.
+///
+/// Contains:
+///   state: One of INCOMPLETE, RUNNING, WAITED or COMPLETE.
+///   waiters: Mutex for waiting on done.
+///   done: Signalled once a waited-on initializer completes.
+///   value: The value, initialized once state is COMPLETE.
+#[derive(Debug)]
+pub struct Once<T> {
+    state: AtomicU8,
+    waiters: Mutex<()>,
+    done: Cond,
+    value: UnsafeCell<MaybeUninit<T>>
+}
+
+unsafe impl<T> Sync for Once<T> where T: Send + Sync {}
+unsafe impl<T> Send for Once<T> where T: Send {}
+
+impl<T> Once<T> {
+    /// Create an uninitialized Once.
+    #[track_caller]
+    pub const fn new() -> Once<T> {
+        Once {
+            state: AtomicU8::new(INCOMPLETE),
+            waiters: Mutex::new(()),
+            done: Cond::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit())
+        }
+    }
+
+    /// Whether the value has been initialized.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Get the value if it has been initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Get the value, initializing it with f if nobody has yet.
+    ///
+    /// If another thread is already initializing the value,
+    /// we block until it is done and f is never called.
+    /// Calling this from within f on the same Once deadlocks.
+    ///
+    /// Must not be called with interrupts disabled unless
+    /// the value is known to be initialized already.
+    pub fn call_once<F>(&self, f: F) -> &T
+    where F: FnOnce() -> T {
+        let mut f = Some(f);
+
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                COMPLETE => {
+                    return unsafe { (*self.value.get()).assume_init_ref() };
+                },
+                INCOMPLETE => {
+                    if self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+                        let f = f.take().unwrap();
+                        return self.complete(f());
+                    }
+                },
+                _ => self.wait()
+            }
+        }
+    }
+
+    /// Initialize the value, unless it has been or is being initialized.
+    ///
+    /// Returns the value back if it could not be stored.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_err() {
+            return Err(value);
+        }
+
+        self.complete(value);
+        Ok(())
+    }
+
+    /// Store the value of a running initializer,
+    /// and wake anyone who waited for it.
+    fn complete(&self, value: T) -> &T {
+        let value = unsafe { (*self.value.get()).write(value) };
+
+        if self.state.swap(COMPLETE, Ordering::AcqRel) == WAITED {
+            // Waiters hold the mutex until they are queued on done,
+            // so none of them can miss the broadcast.
+            let _guard = self.waiters.lock();
+            self.done.broadcastCond();
+        }
+
+        value
+    }
+
+    /// Block until a running initializer completes.
+    fn wait(&self) {
+        let guard = self.waiters.lock();
+
+        // Fails harmlessly if someone else already asked to be woken,
+        // or if the initializer completed in the meantime.
+        let _ = self.state.compare_exchange(RUNNING, WAITED, Ordering::Acquire, Ordering::Acquire);
+
+        // Initializers are kernel code that cannot be interrupted,
+        // so waiting for one ignores signals like waiting for a mutex.
+        let mut guard = guard;
+        while !self.is_completed() {
+            guard = self.done.waitForCondUninterruptible(guard);
+        }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.value.get_mut().assume_init_drop(); }
+        }
+    }
+}
+
+/// A value that is initialized on first access.
+///
+/// Contains:
+///   once: The value itself.
+///   init: The initializer, taken by whoever runs it.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: Cell<Option<F>>
+}
+
+unsafe impl<T, F> Sync for Lazy<T, F> where T: Send + Sync, F: Send {}
+
+impl<T, F> fmt::Debug for Lazy<T, F> where T: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lazy").field("once", &self.once).finish_non_exhaustive()
+    }
+}
+
+impl<T, F> Lazy<T, F> {
+    /// Create a Lazy that will be initialized with init.
+    #[track_caller]
+    pub const fn new(init: F) -> Lazy<T, F> {
+        Lazy {
+            once: Once::new(),
+            init: Cell::new(Some(init))
+        }
+    }
+
+    /// Get the value if it has been initialized.
+    pub fn get(this: &Lazy<T, F>) -> Option<&T> {
+        this.once.get()
+    }
+}
+
+impl<T, F> Lazy<T, F> where F: FnOnce() -> T {
+    /// Get the value, initializing it if nobody has yet.
+    ///
+    /// As with Once::call_once, this blocks while
+    /// another thread runs the initializer.
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.once.call_once(|| {
+            // Only the one initializer that runs can take init.
+            let init = this.init.take().expect("Lazy initializer already taken");
+            init()
+        })
+    }
+}
+
+impl<T, F> Deref for Lazy<T, F> where F: FnOnce() -> T {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
@@ -6,7 +6,8 @@
 use core::cell::{Cell, UnsafeCell};
 use core::mem;
 use core::ops::{Deref, DerefMut};
-use core::ptr::{NonNull, null_mut};
+use core::panic::Location;
+use core::ptr::{self, NonNull, null_mut};
 use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 
 use crate::lprintf;
@@ -15,6 +16,8 @@ use crate::thread::*;
 pub use crate::thread::{ThreadBlock, ThreadHandle};
 
 use super::disable_interrupts::disableInterrupts;
+#[cfg(feature = "lockdep")]
+use super::lockdep;
 
 pub type LockedStatus = bool;
 
@@ -23,12 +26,17 @@ pub const UNLOCKED: LockedStatus = false;
 
 /// An owned lock contains a lock
 /// flag and the current owner.
+///
+/// Under lockdep, it also records where it was created,
+/// which is its lock class.
 #[derive(Debug)]
 pub struct OwnedLock<T> {
     status: AtomicBool,
     owner: AtomicPtr<ThreadBlock>,
     guardCreated: Cell<bool>,
-    data: UnsafeCell<T>
+    data: UnsafeCell<T>,
+    #[cfg(feature = "lockdep")]
+    constructed: lockdep::Site
 }
 
 unsafe impl<T> Sync for OwnedLock<T> where T: Send {}
@@ -42,6 +50,12 @@ fn try_lock(flag: &AtomicBool) -> bool {
     flag.swap(LOCKED, Ordering::AcqRel) == LOCKED
 }
 
+/// The current thread as stored in an owner slot.
+#[inline(always)]
+fn currentThreadPtr() -> *mut ThreadBlock {
+    getCurrentThread().map_or(null_mut(), |t| ptr::from_ref(t).cast_mut())
+}
+
 /// Atomically unlock a flag.
 #[inline(always)]
 fn unlock(flag: &AtomicBool) {
@@ -50,12 +64,15 @@ fn unlock(flag: &AtomicBool) {
 
 impl<T> OwnedLock<T> {
     /// Create an owned lock
+    #[track_caller]
     pub const fn new(data: T) -> OwnedLock<T> {
         OwnedLock {
             status: AtomicBool::new(UNLOCKED),
             owner: AtomicPtr::new(null_mut()),
             guardCreated: Cell::new(false),
-            data: UnsafeCell::new(data)
+            data: UnsafeCell::new(data),
+            #[cfg(feature = "lockdep")]
+            constructed: Location::caller()
         }
     }
 
@@ -74,22 +91,26 @@ impl<T> Drop for OwnedLock<T> {
 
 impl<T> OwnedLock<T> {
     /// Attempt to lock an OwnedLock object
+    ///
+    /// Also succeeds if the lock has been transferred
+    /// to us and we have not taken its guard yet.
     pub fn tryLock(&self) -> Result<OwnedLockGuard<T>, Option<ThreadHandle>> {
+        let current = currentThreadPtr();
+
         if !try_lock(&self.status) {
-            self.owner.store(getCurrentThread().map_or(null_mut(), |p: &ThreadBlock| p.handle().get()), Ordering::Release);
+            self.owner.store(current, Ordering::Release);
         }
 
-        let owner = ThreadHandle::new(self.owner.load(Ordering::Acquire));
-        if owner == getCurrentThread().handle() && !self.guardCreated.get() {
+        if self.owner.load(Ordering::Acquire) == current && !self.guardCreated.get() {
             self.guardCreated.set(true);
             Ok(OwnedLockGuard(self))
         } else {
-            Err(owner)
+            Err(self.owner())
         }
     }
 
     fn owner(&self) -> Option<ThreadHandle> {
-        ThreadHandle::new(self.owner.load(Ordering::Acquire))
+        unsafe { self.owner.load(Ordering::Acquire).as_ref() }.map(ThreadBlock::handle)
     }
 
     /// Waits until we own the lock.
@@ -99,16 +120,44 @@ impl<T> OwnedLock<T> {
     ///
     /// This lock is not re-entrant; if this thread already owns the lock
     /// this function will deadlock.
+    #[track_caller]
     pub fn waitForLock(&self) -> OwnedLockGuard<T> {
-        self.waitForLockWith(|owner| {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self, self.constructed, Location::caller());
+
+        let guard = self.waitForLockWith(|owner| {
             if let None = owner {
-                yieldThread(None);
+                let _ = yieldThread(None);
             } else {
+                self.donateToOwner();
+
                 let guard = disableInterrupts();
                 let owner = self.owner();
-                yieldThreadWithoutInterrupts(&guard, owner);
+                let _ = yieldThreadWithoutInterrupts(&guard, owner.as_ref());
             }
-        })
+        });
+
+        if let Some(thread) = getCurrentThread() {
+            thread.setBlockedOn(ptr::null());
+        }
+
+        guard
+    }
+
+    /// Lend the current thread's priority to the owner of the lock.
+    ///
+    /// Also records that the current thread is waiting on this lock,
+    /// so that priority lent to it is passed on to the owner.
+    /// Waiters should call this each time before yielding,
+    /// and clear the record with setBlockedOn once they have the lock.
+    ///
+    /// Not part of the original C implementation.
+    pub fn donateToOwner(&self) {
+        let Some(thread) = getCurrentThread()
+        else { return; };
+
+        thread.setBlockedOn(&self.owner);
+        donatePriority(&self.owner);
     }
 
     /// Loops and calls the wait function until the lock can be obtained.
@@ -116,17 +165,18 @@ impl<T> OwnedLock<T> {
     /// This lock is not re-entrant; if this thread already owns the lock
     /// this function will deadlock.
     ///
-    /// This function was not part of the original C implemenation,
-    /// but assists us with locking the scheduler itself.
+    /// This function was not part of the original C implemenation.
+    /// Locks that must be taken with interrupts disabled,
+    /// such as the schedule's, should use IrqSpinLock instead.
     pub fn waitForLockWith<F>(&self, wait: F) -> OwnedLockGuard<T>
     where F: Fn(Option<ThreadHandle>) {
-        let thread = getCurrentThread();
+        let thread = currentThreadPtr();
 
         loop {
             match self.tryLock() {
                 Ok(guard) => return guard,
                 Err(owner) => {
-                    if owner == thread {
+                    if owner.as_deref().map_or(null_mut(), |o| ptr::from_ref(o).cast_mut()) == thread {
                         lprintf!("Warning: Guard for lock {} was already created", self);
 
                         while self.guardCreated.get() {
@@ -146,8 +196,20 @@ pub struct OwnedLockGuard<'a, T>(&'a OwnedLock<T>);
 
 impl<T> OwnedLockGuard<'_, T> {
     /// Transfer an owned lock to another thread
-    pub fn transferLockTo(self, thread: NonNull<ThreadBlock>) {
-        self.0.owner.store(thread.as_ptr(), Ordering::Release);
+    pub fn transferLockTo(self, thread: &ThreadBlock) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.0, self.0.constructed);
+
+        // The receiver takes its own guard through tryLock.
+        self.0.guardCreated.set(false);
+        self.0.owner.store(ptr::from_ref(thread).cast_mut(), Ordering::Release);
+
+        // Whoever else waits on the lock now lends to the receiver.
+        if let Some(current) = getCurrentThread() {
+            current.lockReleased();
+        }
+        thread.recomputeInheritedPriority();
+
         mem::forget(self);
     }
 }
@@ -172,8 +234,19 @@ impl<T> DerefMut for OwnedLockGuard<'_, T> {
 
 impl<T> Drop for OwnedLockGuard<'_, T> {
     /// Unlock an OwnedLock
+    ///
+    /// Priority lent to the owner by waiters on this lock is given up,
+    /// while that lent through locks it still holds is kept.
     fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.0, self.0.constructed);
+
+        self.0.guardCreated.set(false);
         self.0.owner.store(null_mut(), Ordering::Release);
         unlock(&self.0.status);
+
+        if let Some(thread) = getCurrentThread() {
+            thread.lockReleased();
+        }
     }
 }
@@ -6,10 +6,13 @@
 //! This code primarily just implements the mode switching.
 
 use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut};
 
+use crate::thread::{Interrupted, deadlineAfter, deadlineReached, get_ticks};
+
 use super::cond::Cond;
-use super::mutex::Mutex;
+use super::mutex::{Mutex, MutexGuard};
 use super::owned_lock::OwnedLock;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -18,12 +21,46 @@ enum RWLockMode {
     Write = 1
 }
 
+/// Which side of a rwlock is let in first under contention.
+///
+/// Not part of the original C implementation,
+/// which was always writer-preferring.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RWLockPolicy {
+    /// New readers may join while writers wait.
+    /// Writers can be starved by a steady stream of readers.
+    ReaderPreferring,
+
+    /// New readers wait while any writer waits.
+    /// Readers can be starved by a steady stream of writers.
+    WriterPreferring,
+
+    /// Readers and writers alternate: readers waiting when
+    /// a writer unlocks all get in before the next writer,
+    /// and readers arriving while a writer waits go after it.
+    PhaseFair
+}
+
+/// Why a timed wait for a rwlock gave up.
+///
+/// Not part of the original C implementation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaitError {
+    /// The deadline passed first.
+    TimedOut,
+
+    /// The waiting thread was sent a signal first.
+    Interrupted
+}
+
 /// Structure for a readers-writers lock
 ///
 /// Contains:
 /// statusMutex: A mutex on the rwlock itself
 /// canWrite: Cond var that a writer waits on until it can write
 /// canRead: Cond var that readers wait on before they can read
+/// canUpgrade: Cond var that an upgrading reader waits on
+///             until it is the only reader
 /// readerCount: Number of readers currently reading
 /// writerWaitlistSize: Number of writers currently waiting
 /// mode: Whether we are in RWLOCK_READ or RWLOCK_WRITE mode. (See rwlock.h)
@@ -32,29 +69,109 @@ pub struct RWLock<T> {
     status: Mutex<RWLockStatus>,
     canWrite: Cond,
     canRead: Cond,
+    canUpgrade: Cond,
     data: UnsafeCell<T>
 }
 
+/// Contains, beyond the original fields:
+/// readerWaitlistSize: Number of readers currently waiting
+/// pendingReaders: Readers let in ahead of waiting writers
+///                 by the last unlocking writer, under PhaseFair
+/// phase: Bumped by each unlocking writer under PhaseFair.
+///        A waiting reader keeps the phase it arrived in as a ticket,
+///        and is one of the pendingReaders once the phase has moved on
+/// upgradeable: Whether an upgradeable reader holds the lock
+/// upgrading: Whether the upgradeable reader is waiting to write
+/// policy: Who is preferred under contention
 #[derive(Debug)]
 struct RWLockStatus {
     readerCount: u32,
     writerWaitlistSize: u32,
-    mode: RWLockMode
+    readerWaitlistSize: u32,
+    pendingReaders: u32,
+    phase: u32,
+    upgradeable: bool,
+    upgrading: bool,
+    mode: RWLockMode,
+    policy: RWLockPolicy
+}
+
+impl RWLockStatus {
+    /// Whether a reader that arrived with the given ticket
+    /// was waiting when the last writer unlocked.
+    fn isPending(&self, ticket: u32) -> bool {
+        ticket != self.phase
+    }
+
+    /// Whether a reader must wait,
+    /// given whether it is one of the pendingReaders.
+    ///
+    /// Under PhaseFair, only the pendingReaders may go
+    /// ahead of a waiting writer.
+    fn readerMustWait(&self, pending: bool) -> bool {
+        if self.mode == RWLockMode::Write || self.upgrading {
+            return true;
+        }
+
+        match self.policy {
+            RWLockPolicy::ReaderPreferring => false,
+            RWLockPolicy::WriterPreferring => self.writerWaitlistSize > 0,
+            RWLockPolicy::PhaseFair => self.writerWaitlistSize > 0 && !pending
+        }
+    }
+
+    /// Whether a writer must wait.
+    fn writerMustWait(&self) -> bool {
+        self.mode == RWLockMode::Write || self.readerCount > 0 || self.pendingReaders > 0
+    }
+
+    /// Whether any waiting reader may go now.
+    fn waitingReaderMayGo(&self) -> bool {
+        !self.readerMustWait(self.pendingReaders > 0)
+    }
+
+    /// Count a reader that has been let in
+    /// with the given ticket.
+    fn admitReader(&mut self, ticket: u32) {
+        if self.isPending(ticket) {
+            self.pendingReaders -= 1;
+        }
+        self.readerCount += 1;
+        self.mode = RWLockMode::Read;
+    }
 }
 
 
 impl<T> RWLock<T> {
     /// Initializes a rwlock.
+    ///
+    /// Writers are preferred, as in the original C implementation.
+    #[track_caller]
     pub const fn new(data: T) -> RWLock<T> {
+        RWLock::with_policy(data, RWLockPolicy::WriterPreferring)
+    }
+
+    /// Initializes a rwlock with the given policy.
+    ///
+    /// Not part of the original C implementation.
+    #[track_caller]
+    pub const fn with_policy(data: T, policy: RWLockPolicy) -> RWLock<T> {
         RWLock {
             status: Mutex::new(RWLockStatus {
                 readerCount: 0,
                 writerWaitlistSize: 0,
-                mode: RWLockMode::Read
+                readerWaitlistSize: 0,
+                pendingReaders: 0,
+                phase: 0,
+                upgradeable: false,
+                upgrading: false,
+                mode: RWLockMode::Read,
+                policy
             }),
             canWrite: Cond::new(),
             canRead: Cond::new(),
-            data: Unsafe::new(data)
+            canUpgrade: Cond::new(),
+            data: UnsafeCell::new(data)
         }
     }
 }
@@ -76,28 +193,106 @@ impl<T> Drop for RWLock<T> {
 
 impl<T> RWLock<T> {
     /// Wait for read access to the rwlock.
-    pub fn lockRead(&self) -> ReadGuard<T> {
+    ///
+    /// Returns Err if the calling thread is sent a signal first.
+    pub fn lockRead(&self) -> Result<ReadGuard<T>, Interrupted> {
+        self.read(true)
+    }
+
+    /// Wait for read access to the rwlock, ignoring signals.
+    ///
+    /// For the kernel's own bounded critical sections,
+    /// which a thread being killed must still be able to enter.
+    ///
+    /// Not part of the original C implementation.
+    pub(crate) fn lockReadUninterruptible(&self) -> ReadGuard<T> {
+        match self.read(false) {
+            Ok(guard) => guard,
+            Err(Interrupted) => unreachable!("uninterruptible rwlock wait interrupted")
+        }
+    }
+
+    /// Wait for read access to the rwlock,
+    /// giving up on a signal if interruptible.
+    fn read(&self, interruptible: bool) -> Result<ReadGuard<T>, Interrupted> {
         let mut status = self.status.lock();
 
         // If anyone currently has write access,
-        // or in accordance with the spec
-        // anyone is waiting for write access,
+        // or the policy says a waiting writer goes first,
         // we must wait until they have obtained
         // access before we get to read.
-        while status.mode == RWLockMode::Write || status.writerWaitlistSize > 0 {
-            status = self.canRead.waitForCond(status)
-        }
+        let ticket = status.phase;
+        status.readerWaitlistSize += 1;
+        status = match self.canRead.waitWhile(status, interruptible, |status| {
+            status.readerMustWait(status.isPending(ticket))
+        }) {
+            Ok(status) => status,
+            Err(Interrupted) => {
+                self.abandonRead(&mut self.status.lock(), ticket);
+                return Err(Interrupted);
+            }
+        };
+        status.readerWaitlistSize -= 1;
 
         // If we are ready to read, we notify that there
         // is an additional reader and set the mode to READ.
-        status.readerCount += 1;
-        status.mode = RWLockMode::Read;
+        status.admitReader(ticket);
 
-        ReadGuard(self, unsafe { &*self.data.get() })
+        Ok(ReadGuard(self, unsafe { &*self.data.get() }))
+    }
+
+    /// Wait for read access to the rwlock that may later
+    /// be upgraded to write access.
+    ///
+    /// Only one upgradeable reader may hold the lock at a time,
+    /// though it shares the lock with ordinary readers.
+    /// Returns Err if the calling thread is sent a signal first.
+    ///
+    /// Not part of the original C implementation.
+    pub fn lockUpgradeable(&self) -> Result<UpgradeableGuard<T>, Interrupted> {
+        let mut status = self.status.lock();
+
+        let ticket = status.phase;
+        status.readerWaitlistSize += 1;
+        status = match self.canRead.wait_while(status, |status| {
+            status.upgradeable || status.readerMustWait(status.isPending(ticket))
+        }) {
+            Ok(status) => status,
+            Err(Interrupted) => {
+                self.abandonRead(&mut self.status.lock(), ticket);
+                return Err(Interrupted);
+            }
+        };
+        status.readerWaitlistSize -= 1;
+
+        status.admitReader(ticket);
+        status.upgradeable = true;
+
+        Ok(UpgradeableGuard(self, unsafe { &*self.data.get() }))
     }
 
     /// Wait for write access to the rwlock.
-    pub fn lockWrite(&self) -> WriteGuard<T> {
+    ///
+    /// Returns Err if the calling thread is sent a signal first.
+    pub fn lockWrite(&self) -> Result<WriteGuard<T>, Interrupted> {
+        self.write(true)
+    }
+
+    /// Wait for write access to the rwlock, ignoring signals.
+    ///
+    /// As lockReadUninterruptible.
+    ///
+    /// Not part of the original C implementation.
+    pub(crate) fn lockWriteUninterruptible(&self) -> WriteGuard<T> {
+        match self.write(false) {
+            Ok(guard) => guard,
+            Err(Interrupted) => unreachable!("uninterruptible rwlock wait interrupted")
+        }
+    }
+
+    /// Wait for write access to the rwlock,
+    /// giving up on a signal if interruptible.
+    fn write(&self, interruptible: bool) -> Result<WriteGuard<T>, Interrupted> {
         let mut status = self.status.lock();
 
         // If we wish to get write access,
@@ -107,16 +302,208 @@ impl<T> RWLock<T> {
         // for write access
         status.writerWaitlistSize += 1;
 
-        while status.mode == RWLockMode::Write || status.readerCount > 0 {
-            status = self.canWrite.waitForCond(status);
-        }
+        status = match self.canWrite.waitWhile(status, interruptible, |status| status.writerMustWait()) {
+            Ok(status) => status,
+            Err(Interrupted) => {
+                self.abandonWrite(&mut self.status.lock());
+                return Err(Interrupted);
+            }
+        };
 
         // Once we get write access, we are no longer on the waitlist,
         // and the mutex is in WRITE mode.
         status.writerWaitlistSize -= 1;
         status.mode = RWLockMode::Write;
 
-        WriteGuard(self, unsafe { &mut *self.data.get() })
+        Ok(WriteGuard(self, unsafe { &mut *self.data.get() }))
+    }
+
+    /// Take read access to the rwlock if it is available
+    /// without waiting.
+    ///
+    /// Fails if the status is locked by someone else,
+    /// rather than waiting for it, and never touches
+    /// the cond vars, so it is safe to call where we must not sleep.
+    ///
+    /// Not part of the original C implementation.
+    pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        let mut status = self.status.tryLock()?;
+
+        // We were not waiting at the last unlock,
+        // so we are never one of the pendingReaders.
+        if status.readerMustWait(false) {
+            return None;
+        }
+
+        let ticket = status.phase;
+        status.admitReader(ticket);
+
+        Some(ReadGuard(self, unsafe { &*self.data.get() }))
+    }
+
+    /// Take write access to the rwlock if it is available
+    /// without waiting.
+    ///
+    /// As try_read, this never waits or touches the cond vars.
+    /// A waiting writer is not jumped ahead of,
+    /// so that try_write cannot starve it.
+    ///
+    /// Not part of the original C implementation.
+    pub fn try_write(&self) -> Option<WriteGuard<T>> {
+        let mut status = self.status.tryLock()?;
+
+        if status.writerMustWait() || status.writerWaitlistSize > 0 {
+            return None;
+        }
+
+        status.mode = RWLockMode::Write;
+
+        Some(WriteGuard(self, unsafe { &mut *self.data.get() }))
+    }
+
+    /// Wait for read access to the rwlock,
+    /// or until ticks timer ticks have passed.
+    ///
+    /// Returns Err if the deadline passed or the calling
+    /// thread was sent a signal first.
+    ///
+    /// Not part of the original C implementation.
+    pub fn read_timeout(&self, ticks: u32) -> Result<ReadGuard<T>, WaitError> {
+        let deadline = deadlineAfter(ticks);
+        let mut status = self.status.lock();
+
+        let ticket = status.phase;
+        status.readerWaitlistSize += 1;
+
+        while status.readerMustWait(status.isPending(ticket)) {
+            let now = get_ticks();
+            if deadlineReached(deadline, now) {
+                self.abandonRead(&mut status, ticket);
+                return Err(WaitError::TimedOut);
+            }
+
+            status = match self.canRead.wait_timeout(status, deadline.wrapping_sub(now)) {
+                Ok((status, _)) => status,
+                Err(Interrupted) => {
+                    self.abandonRead(&mut self.status.lock(), ticket);
+                    return Err(WaitError::Interrupted);
+                }
+            };
+        }
+
+        status.readerWaitlistSize -= 1;
+        status.admitReader(ticket);
+
+        Ok(ReadGuard(self, unsafe { &*self.data.get() }))
+    }
+
+    /// Wait for write access to the rwlock,
+    /// or until ticks timer ticks have passed.
+    ///
+    /// Returns Err if the deadline passed or the calling
+    /// thread was sent a signal first.
+    ///
+    /// Not part of the original C implementation.
+    pub fn write_timeout(&self, ticks: u32) -> Result<WriteGuard<T>, WaitError> {
+        let deadline = deadlineAfter(ticks);
+        let mut status = self.status.lock();
+
+        status.writerWaitlistSize += 1;
+
+        while status.writerMustWait() {
+            let now = get_ticks();
+            if deadlineReached(deadline, now) {
+                self.abandonWrite(&mut status);
+                return Err(WaitError::TimedOut);
+            }
+
+            status = match self.canWrite.wait_timeout(status, deadline.wrapping_sub(now)) {
+                Ok((status, _)) => status,
+                Err(Interrupted) => {
+                    self.abandonWrite(&mut self.status.lock());
+                    return Err(WaitError::Interrupted);
+                }
+            };
+        }
+
+        status.writerWaitlistSize -= 1;
+        status.mode = RWLockMode::Write;
+
+        Ok(WriteGuard(self, unsafe { &mut *self.data.get() }))
+    }
+
+    /// Stop waiting for read access without getting it.
+    ///
+    /// Must be called with the status locked.
+    fn abandonRead(&self, status: &mut RWLockStatus, ticket: u32) {
+        status.readerWaitlistSize -= 1;
+
+        // If we were counted as a pending reader,
+        // writers would wait for us forever.
+        if status.isPending(ticket) {
+            status.pendingReaders -= 1;
+            self.wakeWriterIfIdle(status);
+        }
+    }
+
+    /// Stop waiting for write access without getting it.
+    ///
+    /// Must be called with the status locked.
+    fn abandonWrite(&self, status: &mut RWLockStatus) {
+        status.writerWaitlistSize -= 1;
+
+        // We may have been signaled just as we gave up,
+        // so pass access on as an unlock would.
+        // Readers held back only by us may also go ahead.
+        self.wakeWriterIfIdle(status);
+        if status.waitingReaderMayGo() {
+            self.canRead.broadcastCond();
+        }
+    }
+
+    /// Signal a waiting writer if nothing holds it back.
+    fn wakeWriterIfIdle(&self, status: &RWLockStatus) {
+        if status.writerWaitlistSize > 0 && !status.writerMustWait() {
+            self.canWrite.signalCond();
+        }
+    }
+
+    /// Let waiters in once a writer gives up write access.
+    ///
+    /// Must be called with the status locked and mode
+    /// already switched away from WRITE.
+    fn wakeAfterWrite(&self, status: &mut MutexGuard<RWLockStatus>) {
+        match status.policy {
+            // If anyone is waiting for write access, pass off to them,
+            // otherwise everyone waiting for read access can.
+            RWLockPolicy::WriterPreferring => {
+                if status.writerWaitlistSize > 0 {
+                    self.wakeWriterIfIdle(status);
+                } else {
+                    self.canRead.broadcastCond();
+                }
+            },
+            RWLockPolicy::ReaderPreferring => {
+                if status.readerWaitlistSize > 0 {
+                    self.canRead.broadcastCond();
+                } else {
+                    self.wakeWriterIfIdle(status);
+                }
+            },
+            // Everyone already waiting to read gets a turn
+            // before the next writer, while readers arriving
+            // from now on wait behind any queued writer.
+            RWLockPolicy::PhaseFair => {
+                status.phase = status.phase.wrapping_add(1);
+                status.pendingReaders = status.readerWaitlistSize;
+
+                if status.pendingReaders > 0 {
+                    self.canRead.broadcastCond();
+                } else {
+                    self.wakeWriterIfIdle(status);
+                }
+            }
+        }
     }
 }
 
@@ -126,6 +513,13 @@ pub struct ReadGuard<'a, T>(&'a RWLock<T>, &'a T);
 #[derive(Debug)]
 pub struct WriteGuard<'a, T>(&'a RWLock<T>, &'a mut T);
 
+/// Read access that can be upgraded to write access
+/// without letting a writer in between.
+///
+/// Not part of the original C implementation.
+#[derive(Debug)]
+pub struct UpgradeableGuard<'a, T>(&'a RWLock<T>, &'a T);
+
 
 impl<T> Deref for ReadGuard<'_, T> {
     type Target = T;
@@ -149,6 +543,14 @@ impl<T> DerefMut for WriteGuard<'_, T> {
     }
 }
 
+impl<T> Deref for UpgradeableGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.1
+    }
+}
+
 impl<T> Drop for ReadGuard<'_, T> {
     /// Unlock access to the rwlock.
     fn drop(&mut self) {
@@ -158,8 +560,12 @@ impl<T> Drop for ReadGuard<'_, T> {
         status.readerCount -= 1;
 
         // If no one else is reading, someone may write.
+        // If only the upgradeable reader is left and it wants
+        // to write, it may.
         if status.readerCount == 0 {
             self.0.canWrite.signalCond();
+        } else if status.readerCount == 1 && status.upgrading {
+            self.0.canUpgrade.signalCond();
         }
     }
 }
@@ -174,13 +580,26 @@ impl<T> Drop for WriteGuard<'_, T> {
         // This is ok, since we still have the status locked.
         status.mode = RWLockMode::Read;
 
-        // If anyone is waiting for write access, pass off to them,
-        // otherwise everyone waiting for read access can.
-        if status.writerWaitlistSize > 0 {
+        self.0.wakeAfterWrite(&mut status);
+    }
+}
+
+impl<T> Drop for UpgradeableGuard<'_, T> {
+    /// Unlock access to the rwlock.
+    ///
+    /// Others waiting to become the upgradeable reader
+    /// wait with the ordinary readers, so they are woken too.
+    fn drop(&mut self) {
+        let mut status = self.0.status.lock();
+
+        status.readerCount -= 1;
+        status.upgradeable = false;
+
+        if status.readerCount == 0 {
             self.0.canWrite.signalCond();
-        } else {
-            self.0.canRead.broadcastCond();
         }
+
+        self.0.canRead.broadcastCond();
     }
 }
 
@@ -188,13 +607,66 @@ impl<'a, T> WriteGuard<'a, T> {
     /// Downgrades access to read from write.
     ///
     /// This simply switches the state between modes
-    /// without releasing the lock.
+    /// without releasing the lock, then lets in
+    /// any readers the policy allows alongside us.
     pub fn downgradeRWLock(self) -> ReadGuard<'a, T> {
-        let mut status = self.0.status.lock();
+        // Our write access becomes read access,
+        // so the guard must not unlock.
+        let this = ManuallyDrop::new(self);
+        let lock = this.0;
+
+        let mut status = lock.status.lock();
 
         status.readerCount += 1;
         status.mode = RWLockMode::Read;
 
-        ReadGuard(self.0, &self.1)
+        if status.waitingReaderMayGo() {
+            lock.canRead.broadcastCond();
+        }
+
+        ReadGuard(lock, unsafe { &*lock.data.get() })
+    }
+}
+
+impl<'a, T> UpgradeableGuard<'a, T> {
+    /// Upgrades access to write from read.
+    ///
+    /// New readers are held back while we wait for the
+    /// current ones to leave, and since we stay a reader
+    /// until then, no writer can get in first.
+    ///
+    /// If the calling thread is sent a signal first,
+    /// the readers held back are let in again and
+    /// our read access is given back along with the error.
+    ///
+    /// Not part of the original C implementation.
+    pub fn upgrade(self) -> Result<WriteGuard<'a, T>, (UpgradeableGuard<'a, T>, Interrupted)> {
+        let this = ManuallyDrop::new(self);
+        let lock = this.0;
+
+        let mut status = lock.status.lock();
+
+        status.upgrading = true;
+        status = match lock.canUpgrade.wait_while(status, |status| status.readerCount > 1) {
+            Ok(status) => status,
+            Err(Interrupted) => {
+                let mut status = lock.status.lock();
+                status.upgrading = false;
+
+                if status.waitingReaderMayGo() {
+                    lock.canRead.broadcastCond();
+                }
+
+                drop(status);
+                return Err((ManuallyDrop::into_inner(this), Interrupted));
+            }
+        };
+
+        status.upgrading = false;
+        status.upgradeable = false;
+        status.readerCount -= 1;
+        status.mode = RWLockMode::Write;
+
+        Ok(WriteGuard(lock, unsafe { &mut *lock.data.get() }))
     }
 }
@@ -0,0 +1,236 @@
+//! Implementation of counting semaphores.
+//!
+//! As with mutexes, requesters that cannot be granted
+//! their permits immediately add a node on their own
+//! stack to a queue, so waiting needs no allocation.
+//! Permits are handed out in queue order: whoever
+//! releases permits grants them to waiters from the front,
+//! removing them from the queue and waking them through
+//! the same flag protocol that cond vars use.
+//!
+//! Not part of the original C implementation.
+
+use core::mem::ManuallyDrop;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::lprintf;
+use crate::thread::{Interrupted, ThreadHandle, blockUntil, get_ticks, getCurrentThread, scheduleThread};
+use crate::variable_queue::*;
+
+use super::cond::{DO_NOT_DESCHEDULE, TRY_TO_DESCHEDULE};
+use super::disable_interrupts::disableInterrupts;
+use super::owned_lock::OwnedLock;
+
+/// A node in the semaphore wait queue.
+///
+/// Contains:
+///   link: Queue link to use in variable queue macros.
+///   wanted: Number of permits requested.
+///   thread: The waiting thread.
+///   granted: Set to DO_NOT_DESCHEDULE once the permits are granted.
+#[derive(Debug)]
+pub struct SemaphoreWaitNode {
+    link: Link<SemaphoreWaitNode>,
+    wanted: usize,
+    thread: Option<ThreadHandle>,
+    granted: AtomicBool
+}
+
+unsafe impl Send for SemaphoreWaitNode {}
+
+pub type SemaphoreWaitList = Head<SemaphoreWaitNode>;
+
+/// Semaphore structure
+///
+/// Contains:
+/// state: A lock on the available permits and the wait queue.
+#[derive(Debug)]
+pub struct Semaphore {
+    state: OwnedLock<SemaphoreState>
+}
+
+#[derive(Debug)]
+struct SemaphoreState {
+    permits: usize,
+    waitList: SemaphoreWaitList
+}
+
+
+impl Semaphore {
+    /// Create a semaphore with some permits available.
+    #[track_caller]
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: OwnedLock::new(SemaphoreState {
+                permits,
+                waitList: Head::new()
+            })
+        }
+    }
+}
+
+impl Drop for Semaphore {
+    /// Destroys the semaphore.
+    ///
+    /// Checks that no one is waiting
+    /// so that an illegal operation can be noticed quickly.
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+
+        if state.waitList.front().is_some() {
+            lprintf!("ILLEGAL:
+                Attempt to destroy semaphore while being waited for: {}.\n",
+                self
+            );
+        }
+
+        state.waitList = Head::new();
+    }
+}
+
+impl Semaphore {
+    /// Wait for a permit.
+    ///
+    /// Returns Err if the calling thread is sent a signal first.
+    pub fn acquire(&self) -> Result<SemaphorePermit, Interrupted> {
+        self.acquire_many(1)
+    }
+
+    /// Wait until count permits can be taken at once.
+    ///
+    /// Requesters are served in order, so a large request
+    /// at the front of the queue holds back smaller ones
+    /// behind it rather than being starved by them.
+    ///
+    /// If the calling thread is sent a signal first,
+    /// it leaves the queue, letting in anyone it held back,
+    /// and returns Err.
+    /// If the permits were granted at the same time,
+    /// they are kept and the signal is consumed.
+    pub fn acquire_many(&self, count: usize) -> Result<SemaphorePermit, Interrupted> {
+        let mut state = self.state.waitForLock();
+
+        // If no one is waiting and there are enough permits,
+        // no need to wait.
+        if state.waitList.front().is_none() && state.permits >= count {
+            state.permits -= count;
+            return Ok(SemaphorePermit(self, count));
+        }
+
+        let thisThreadWaitInfo = pin!(SemaphoreWaitNode {
+            link: Link::new(),
+            wanted: count,
+            thread: getCurrentThread().map(|t| t.handle()),
+            granted: AtomicBool::new(TRY_TO_DESCHEDULE)
+        });
+
+        let thisThreadWaitInfo = unsafe {
+            insert_tail!(&mut state.waitList, thisThreadWaitInfo.as_ref(), link)
+        };
+
+        // Release access to the queue
+        drop(state);
+
+        // Whoever grants us the permits removes us from the queue
+        // before setting granted, so once it is set we are done.
+        let blockedSince = get_ticks();
+        let disabledInterrupts = disableInterrupts();
+        let result = blockUntil(&disabledInterrupts, &thisThreadWaitInfo.granted);
+        drop(disabledInterrupts);
+
+        if let Some(thread) = getCurrentThread() {
+            thread.chargeBlocked(blockedSince);
+        }
+
+        if result.is_err() {
+            let mut state = self.state.waitForLock();
+
+            // If we are still queued, no one granted us permits.
+            if thisThreadWaitInfo.link.in_queue() {
+                remove!(&mut state.waitList, thisThreadWaitInfo, link);
+                Semaphore::grantWaiters(&mut state);
+                return Err(Interrupted);
+            }
+        }
+
+        Ok(SemaphorePermit(self, count))
+    }
+
+    /// Take a permit if one is available without waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit> {
+        let mut state = self.state.tryLock().ok()?;
+
+        if state.waitList.front().is_none() && state.permits > 0 {
+            state.permits -= 1;
+            Some(SemaphorePermit(self, 1))
+        } else {
+            None
+        }
+    }
+
+    /// Make count more permits available.
+    ///
+    /// Permits are granted to waiters at the front of the queue
+    /// for as long as there are enough for their requests.
+    pub fn release(&self, count: usize) {
+        let mut state = self.state.waitForLock();
+        state.permits += count;
+
+        Semaphore::grantWaiters(&mut state);
+    }
+
+    /// Grant permits to waiters at the front of the queue
+    /// for as long as there are enough for their requests.
+    ///
+    /// This may only be called when you have the state locked.
+    fn grantWaiters(state: &mut SemaphoreState) {
+        while let Some(next) = state.waitList.front_ptr() {
+            let next = unsafe { &*next };
+            if next.wanted > state.permits {
+                break;
+            }
+
+            state.permits -= next.wanted;
+            remove!(&mut state.waitList, next, link);
+
+            // Once granted is set, the waiter may return
+            // and free its node at any time.
+            let thread = next.thread.clone();
+            next.granted.store(DO_NOT_DESCHEDULE, Ordering::Release);
+
+            if let Some(thread) = thread {
+                let _ = scheduleThread(&disableInterrupts(), &thread);
+            }
+        }
+    }
+
+    /// Number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.state.waitForLock().permits
+    }
+}
+
+/// Permits taken from a semaphore,
+/// which are released when dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit<'a>(&'a Semaphore, usize);
+
+impl SemaphorePermit<'_> {
+    /// Number of permits held.
+    pub fn count(&self) -> usize {
+        self.1
+    }
+
+    /// Keep the permits taken without releasing them.
+    pub fn forget(self) {
+        let _ = ManuallyDrop::new(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    /// Return the permits to the semaphore.
+    fn drop(&mut self) {
+        self.0.release(self.1);
+    }
+}
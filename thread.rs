@@ -5,21 +5,37 @@ mod thread_internal;
 mod continuation;
 mod context_switch;
 mod scheduler;
+mod policy;
+mod timer;
+mod sleep;
+mod idle;
+mod stats;
+mod kernel_thread;
+mod join;
+mod swexn;
+mod exception;
+mod signal;
 mod thread_collection;
 mod manager;
+mod tid_index;
 
 use core::ops::{Deref, DerefMut};
 use core::pin::Pin;
-use core::ptr::{NonNull, null_mut};
+use core::ptr::{self, NonNull, null_mut};
 use core::sync::atomic::Ordering;
 
 /// Data structure containing information about a thread
 pub use thread_internal::ThreadBlock;
 
 /// Thread Management API
-/*pub use manager::{
-    installThreadManager,
-}*/
+pub use manager::{
+    exitThread,
+    reapThreads
+};
+
+/// Kernel Thread API
+pub use kernel_thread::spawn_kernel_thread;
+pub use join::{JoinHandle, spawn_joinable_kernel_thread};
 
 /// Thread Collection API
 pub use thread_collection::ThreadCollection;
@@ -28,8 +44,59 @@ pub use thread_collection::ThreadCollection;
 pub use scheduler::{
     scheduleThread,
     descheduleThread,
-    blockUntil
+    blockUntil,
+    blockUntilUninterruptible,
+    deschedule,
+    make_runnable,
+    r#yield,
+    donatePriority,
+    NUM_PRIORITIES,
+    DEFAULT_PRIORITY
+};
+
+/// Timer API
+pub use timer::{
+    initTimer,
+    get_ticks,
+    TICKS_PER_SECOND
+};
+
+/// Statistics API
+pub use stats::ThreadStatsSnapshot;
+pub use manager::snapshotThreadStats;
+
+/// Idle Thread API
+pub use idle::{
+    initIdleThread,
+    getIdleTicks
+};
+
+/// Timer Wait Queue API
+pub use sleep::{
+    TimedOut,
+    TimerNode,
+    addTimer,
+    cancelTimer,
+    deadlineAfter,
+    deadlineReached,
+    sleep
+};
+
+/// Signal API
+pub use signal::{
+    Interrupted,
+    checkPendingSignals,
+    signalThread,
+    SIGNAL_INTERRUPT,
+    SIGNAL_KILL
+};
+
+/// User Exception Handler API
+pub use swexn::{
+    deliverException,
+    swexn
 };
+pub use exception::initExceptions;
 
 /// Mode Switch
 pub use continuation::exitKernelMode;
@@ -100,6 +167,81 @@ impl Drop for ThreadHandle {
     }
 }
 
+/// A handle to a thread that does not keep it alive.
+///
+/// Thread blocks are recycled once their thread exits,
+/// so a plain pointer to one could end up observing
+/// an unrelated thread that reused the block.
+/// A weak handle remembers the generation of the block
+/// it was created from, and can only be upgraded to a
+/// ThreadHandle while that generation is current.
+///
+/// Not in the original C implementation.
+#[derive(Debug)]
+pub struct WeakThreadHandle {
+    thread: NonNull<ThreadBlock>,
+    generation: u32
+}
+
+unsafe impl Send for WeakThreadHandle {}
+
+impl ThreadBlock {
+    pub fn weakHandle(&self) -> WeakThreadHandle {
+        self.weakCount.fetch_add(1, Ordering::AcqRel);
+        WeakThreadHandle {
+            thread: NonNull::from_ref(self),
+            generation: self.generation.load(Ordering::Acquire)
+        }
+    }
+}
+
+impl ThreadHandle {
+    pub fn downgrade(&self) -> WeakThreadHandle {
+        self.weakHandle()
+    }
+}
+
+impl WeakThreadHandle {
+    /// Obtain a handle to the thread, if it has not exited.
+    pub fn upgrade(&self) -> Option<ThreadHandle> {
+        // The reference is taken before checking the generation,
+        // so that the block cannot be recycled between the two.
+        let handle = unsafe { self.thread.as_ref() }.handle();
+
+        if handle.generation.load(Ordering::Acquire) == self.generation {
+            Some(handle)
+        } else {
+            // The thread has exited, and the block may already be
+            // serving another; dropping the handle backs out our
+            // reference, which recycling leaves untouched.
+            drop(handle);
+            None
+        }
+    }
+
+    /// Check whether this handle was created from the given thread.
+    pub fn refersTo(&self, thread: &ThreadBlock) -> bool {
+        ptr::eq(self.thread.as_ptr(), thread)
+            && thread.generation.load(Ordering::Acquire) == self.generation
+    }
+}
+
+impl Clone for WeakThreadHandle {
+    fn clone(&self) -> WeakThreadHandle {
+        unsafe { self.thread.as_ref() }.weakCount.fetch_add(1, Ordering::AcqRel);
+        WeakThreadHandle {
+            thread: self.thread,
+            generation: self.generation
+        }
+    }
+}
+
+impl Drop for WeakThreadHandle {
+    fn drop(&mut self) {
+        unsafe { self.thread.as_ref() }.weakCount.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 /// An (owned) pointer to the full thread allocation, including the kernel stack.
 ///
 /// Not in the original C implementation, which directly used pointers to ThreadBlocks
@@ -2,17 +2,26 @@
 
 use core::ffi::c_void;
 use core::ptr::{self, NonNull, null_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
 use super::continuation::{callWithCurrentContinuation, continueFromContinuation};
+use super::idle::getIdleThread;
+use super::manager::queueDeadThread;
+use super::scheduler::yieldToThread;
 use super::thread_internal::{KERNEL_STACK_SIZE, getNextThread};
 use super::{continuation::Continuation, *};
+use _410kern::cr::get_cr3;
 use crate::sync::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
 use crate::task::TaskBlock;
+use crate::virtual_memory::kernelDirectory;
 
-static mut _currentThread: *mut ThreadBlock = null_mut();
+/// The thread that is currently running.
+///
+/// Only changed by continueThread, with interrupts disabled.
+static _currentThread: AtomicPtr<ThreadBlock> = AtomicPtr::new(null_mut());
 
 /// Obtain the currently running thread.
 pub fn getCurrentThread<'a>() -> Option<&'a ThreadBlock> {
-    unsafe { NonNull::new(_currentThread).map(|p| p.as_ref()) }
+    unsafe { NonNull::new(_currentThread.load(Ordering::Acquire)).map(|p| p.as_ref()) }
 }
 
 /// Obtain the currently set task.
@@ -23,9 +32,11 @@ pub fn getCurrentTask() -> Option<NonNull<TaskBlock>> {
 
 /// Update a thread to store the given continuation.
 fn saveContinuationTo(thread: &mut ThreadBlock, cont: Continuation) {
-    thread.kernelStackOffset = unsafe { cont.byte_offset_from_unsigned(thread) };
-    unimplemented!()
-    // thread.inKernelDirectory = (get_cr3() == kernelDirectory())
+    thread.kernelStackOffset.set(unsafe { cont.byte_offset_from_unsigned(thread) });
+
+    // The kernel directory is direct mapped,
+    // so cr3 holds the same address when we are in it.
+    thread.inKernelDirectory.set(unsafe { get_cr3() } as usize == kernelDirectory().addr());
 }
 
 /// Save the given continuation and switch to the given
@@ -50,7 +61,8 @@ unsafe extern "cdecl" fn saveAndContinue(cont: Continuation, args: *mut c_void)
 /// Context switch to a thread.
 ///
 /// If thread is None, will switch to the next
-/// thread in the schedule.
+/// thread in the schedule, or to the idle thread
+/// if the schedule is empty.
 /// Otherwise, will try to switch to the given
 /// thread. If it is not scheduled,
 /// return -1.
@@ -64,14 +76,32 @@ pub fn yieldThread(thread: Option<&ThreadHandle>) -> Result<(), ()> {
 /// This function was not part of the original C implementation
 pub fn yieldThreadWithoutInterrupts(disabledInterrupts: &DisabledInterruptsGuard, thread: Option<&ThreadHandle>)
                                    -> Result<(), ()> {
-                                       let thread = thread.or_else(|| getNextThread(disabledInterrupts));
-    let Some(thread) = thread
-    else {
-        drop(disabledInterrupts);
-        return Err(());
+    match thread {
+        Some(thread) => yieldThreadTo(disabledInterrupts, thread),
+        None => switchToNextThread(disabledInterrupts, true)
+    }
+}
+
+/// Context switch away from a thread that has used up its quantum.
+///
+/// Not part of the original C implementation.
+pub(super) fn preemptThread(disabledInterrupts: &DisabledInterruptsGuard) -> Result<(), ()> {
+    switchToNextThread(disabledInterrupts, false)
+}
+
+/// Switch to the next thread in the schedule,
+/// or to the idle thread if the schedule is empty.
+fn switchToNextThread(disabledInterrupts: &DisabledInterruptsGuard, voluntary: bool) -> Result<(), ()> {
+    // The handle must not be held across the switch,
+    // as we may never return to drop it.
+    let next = match getNextThread(disabledInterrupts) {
+        Some(next) => ptr::from_ref(&*next),
+        // Nothing is runnable, so wait in the idle thread.
+        None => ptr::from_ref(getIdleThread().ok_or(())?)
     };
 
-    yieldThreadTo(&disabledInterrupts, thread)
+    unsafe { switchToThread(disabledInterrupts, &*next, voluntary) };
+    Ok(())
 }
 
 /// This function was not part of the original C implementation
@@ -79,14 +109,35 @@ pub fn yieldThreadWithoutInterrupts(disabledInterrupts: &DisabledInterruptsGuard
 /// While this function requires interrupts disabled to do its work and promises to
 /// return with them disabled, interrupts will be re-enabled while other threads run.
 pub fn yieldThreadTo(disabledInterrupts: &DisabledInterruptsGuard, thread: &ThreadBlock) -> Result<(), ()> {
-    if !thread.scheduled.get() {
-        Err(())
-    } else {
-        unsafe {
-            let ptr = ptr::from_ref(thread).cast_mut().cast();
-            callWithCurrentContinuation(saveAndContinue, thread);
+    if !thread.scheduled.load(Ordering::Acquire) {
+        return Err(());
+    }
+
+    yieldToThread(disabledInterrupts, thread)?;
+    switchToThread(disabledInterrupts, thread, true);
+    Ok(())
+}
+
+/// Save the current thread and continue another.
+///
+/// voluntary is whether the current thread chose to give up
+/// the CPU, rather than being preempted, for its statistics.
+///
+/// Returns once the current thread is continued again.
+fn switchToThread(disabledInterrupts: &DisabledInterruptsGuard, thread: &ThreadBlock, voluntary: bool) {
+    if let Some(curr) = getCurrentThread() {
+        if ptr::eq(curr, thread) {
+            return;
         }
-        Ok(())
+
+        debug_assert!(curr.spinLocksHeld.get() == 0, "IrqSpinLock held across a context switch");
+
+        curr.stats.chargeSwitch(voluntary);
+    }
+
+    unsafe {
+        let ptr = ptr::from_ref(thread).cast_mut().cast();
+        callWithCurrentContinuation(saveAndContinue, ptr);
     }
 }
 
@@ -94,11 +145,19 @@ pub fn yieldThreadTo(disabledInterrupts: &DisabledInterruptsGuard, thread: &Thre
 ///
 /// Must be called while interrupts are disabled.
 ///
+/// If the thread we are switching away from has exited,
+/// it is handed to the dead queue here, as this is the last
+/// point at which its kernel stack is in use.
+///
 /// Does not return. All resources will be leaked if not manually dropped before calling.
 pub unsafe fn continueThread(thread: &ThreadBlock) -> ! {
     unsafe {
-        let oldThread = _currentThread;
-        _currentThread = thread;
+        let oldThread = _currentThread.swap(ptr::from_ref(thread).cast_mut(), Ordering::AcqRel);
+
+        if let Some(old) = oldThread.as_ref() && old.exited.get() && !ptr::eq(old, thread) {
+            let disabledInterrupts = disableInterrupts();
+            queueDeadThread(&disabledInterrupts, Thread::from_raw(old));
+        }
 
         set_esp0(thread.byte_add(KERNEL_STACK_SIZE).addr().get());
         let cont: Continuation = thread.byte_add(thread.as_mut().kernelStackOffset).as_ptr().cast();
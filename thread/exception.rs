@@ -0,0 +1,187 @@
+//! Processor exception handling.
+//!
+//! Page faults are first offered to the VM system,
+//! which resolves those that are part of normal operation,
+//! such as writes to copy-on-write pages.
+//! Other exceptions taken in user mode are offered to
+//! the thread's swexn handler; if it has none,
+//! the thread is killed.
+//! Exceptions taken in the kernel are fatal, except for
+//! page faults on user addresses, which kill the thread.
+//!
+//! Not part of the original C implementation,
+//! which left exceptions to the default 410 handlers.
+
+use core::arch::naked_asm;
+
+use _410kern::cr::get_cr2;
+use _410kern::idt::{
+    IDT_AC, IDT_BP, IDT_BR, IDT_CSO, IDT_DB, IDT_DE, IDT_GP, IDT_MC, IDT_MF,
+    IDT_NM, IDT_NP, IDT_OF, IDT_PF, IDT_SS, IDT_TS, IDT_UD, IDT_XF
+};
+use _410kern::seg::{SEGSEL_KERNEL_CS, SEGSEL_USER_CS};
+
+use crate::idt_entry::{HARDWARE_PRIVILEGE, IDT, INTERRUPT_GATE, USER_PRIVILEGE};
+use crate::registers::ExceptionState;
+use crate::virtual_memory::{LOGIC_NULL, USER_MEM_START, resolvePageFault};
+
+use super::manager::exitThread;
+use super::signal::checkPendingSignals;
+use super::swexn::deliverException;
+
+/// Define the entry point for an exception.
+///
+/// The entry point saves the interrupted state in the shape
+/// of an ExceptionState, pushing a zero error code first
+/// for exceptions that do not push one,
+/// and passes it to exceptionHandler along with the cause.
+macro_rules! exceptionWrapper {
+    ($name:ident, $cause:expr, errorCode) => {
+        #[unsafe(naked)]
+        unsafe extern "cdecl" fn $name() {
+            naked_asm!(
+                "push %ds",
+                "push %es",
+                "push %fs",
+                "push %gs",
+                "pusha",
+                "push ${cause}",
+                "jmp {common}",
+                cause = const $cause,
+                common = sym exceptionCommon,
+                options(att_syntax)
+            );
+        }
+    };
+    ($name:ident, $cause:expr) => {
+        #[unsafe(naked)]
+        unsafe extern "cdecl" fn $name() {
+            naked_asm!(
+                "push $0",
+                "push %ds",
+                "push %es",
+                "push %fs",
+                "push %gs",
+                "pusha",
+                "push ${cause}",
+                "jmp {common}",
+                cause = const $cause,
+                common = sym exceptionCommon,
+                options(att_syntax)
+            );
+        }
+    };
+}
+
+exceptionWrapper!(divideErrorWrapper, IDT_DE);
+exceptionWrapper!(debugWrapper, IDT_DB);
+exceptionWrapper!(breakpointWrapper, IDT_BP);
+exceptionWrapper!(overflowWrapper, IDT_OF);
+exceptionWrapper!(boundRangeWrapper, IDT_BR);
+exceptionWrapper!(invalidOpcodeWrapper, IDT_UD);
+exceptionWrapper!(deviceNotAvailableWrapper, IDT_NM);
+exceptionWrapper!(segmentOverrunWrapper, IDT_CSO);
+exceptionWrapper!(invalidTssWrapper, IDT_TS, errorCode);
+exceptionWrapper!(segmentNotPresentWrapper, IDT_NP, errorCode);
+exceptionWrapper!(stackFaultWrapper, IDT_SS, errorCode);
+exceptionWrapper!(generalProtectionWrapper, IDT_GP, errorCode);
+exceptionWrapper!(pageFaultWrapper, IDT_PF, errorCode);
+exceptionWrapper!(floatingPointWrapper, IDT_MF);
+exceptionWrapper!(alignmentCheckWrapper, IDT_AC, errorCode);
+exceptionWrapper!(machineCheckWrapper, IDT_MC);
+exceptionWrapper!(simdWrapper, IDT_XF);
+
+/// Calls the exception handler with the state saved by a wrapper,
+/// then returns from the exception with that state,
+/// which the handler may have redirected.
+#[unsafe(naked)]
+unsafe extern "cdecl" fn exceptionCommon() {
+    naked_asm!(
+        "lea 4(%esp), %eax",
+        "push %eax",
+        "call {handler}",
+        "add $8, %esp",
+        "popa",
+        "pop %gs",
+        "pop %fs",
+        "pop %es",
+        "pop %ds",
+        "add $4, %esp",
+        "iret",
+        handler = sym exceptionHandler,
+        options(att_syntax)
+    );
+}
+
+/// Install handlers for every exception a thread can cause.
+///
+/// Breakpoints and overflows may be raised
+/// on purpose by user code, so those gates are
+/// accessible from user mode.
+pub fn initExceptions() {
+    let gates: [(usize, unsafe extern "cdecl" fn(), u8); 17] = [
+        (IDT_DE as usize, divideErrorWrapper, HARDWARE_PRIVILEGE),
+        (IDT_DB as usize, debugWrapper, HARDWARE_PRIVILEGE),
+        (IDT_BP as usize, breakpointWrapper, USER_PRIVILEGE),
+        (IDT_OF as usize, overflowWrapper, USER_PRIVILEGE),
+        (IDT_BR as usize, boundRangeWrapper, HARDWARE_PRIVILEGE),
+        (IDT_UD as usize, invalidOpcodeWrapper, HARDWARE_PRIVILEGE),
+        (IDT_NM as usize, deviceNotAvailableWrapper, HARDWARE_PRIVILEGE),
+        (IDT_CSO as usize, segmentOverrunWrapper, HARDWARE_PRIVILEGE),
+        (IDT_TS as usize, invalidTssWrapper, HARDWARE_PRIVILEGE),
+        (IDT_NP as usize, segmentNotPresentWrapper, HARDWARE_PRIVILEGE),
+        (IDT_SS as usize, stackFaultWrapper, HARDWARE_PRIVILEGE),
+        (IDT_GP as usize, generalProtectionWrapper, HARDWARE_PRIVILEGE),
+        (IDT_PF as usize, pageFaultWrapper, HARDWARE_PRIVILEGE),
+        (IDT_MF as usize, floatingPointWrapper, HARDWARE_PRIVILEGE),
+        (IDT_AC as usize, alignmentCheckWrapper, HARDWARE_PRIVILEGE),
+        (IDT_MC as usize, machineCheckWrapper, HARDWARE_PRIVILEGE),
+        (IDT_XF as usize, simdWrapper, HARDWARE_PRIVILEGE)
+    ];
+
+    for (entry, wrapper, privilege) in gates {
+        unsafe {
+            *IDT.add(entry) = INTERRUPT_GATE(wrapper as u32, SEGSEL_KERNEL_CS, privilege);
+        }
+    }
+}
+
+/// Handle an exception.
+///
+/// A page fault the VM system can resolve is retried.
+/// Otherwise, a user thread's exception handler, if registered, is run
+/// by redirecting the state we return to; otherwise the
+/// faulting thread is killed.
+///
+/// Runs with interrupts disabled, as it is entered through an interrupt gate.
+extern "cdecl" fn exceptionHandler(state: &mut ExceptionState, cause: u32) {
+    let cr2 = if cause == IDT_PF as u32 { unsafe { get_cr2() } } else { 0 };
+
+    // Returning retries the faulting access,
+    // from either user or kernel mode.
+    if cause == IDT_PF as u32
+        && unsafe { resolvePageFault(LOGIC_NULL.offset(cr2 as usize), state.err) } {
+        return;
+    }
+
+    // Kernel mode exceptions did not push esp and ss.
+    // A fault on a user address, such as the exception stack
+    // we deliver onto, is the thread's fault, so it is killed
+    // as for a user mode fault.
+    // Anything else is a bug in the kernel rather than in the thread.
+    if state.cs != SEGSEL_USER_CS as u32 {
+        if cause == IDT_PF as u32 && cr2 as usize >= USER_MEM_START {
+            exitThread();
+        }
+
+        panic!("exception {} in kernel mode at {:#x}, cr2 {:#x}", cause, state.eip, cr2);
+    }
+
+    if deliverException(cause, cr2, state) {
+        // We return to user mode without going through exitKernelMode.
+        checkPendingSignals();
+        return;
+    }
+
+    exitThread();
+}
@@ -0,0 +1,69 @@
+//! The idle thread.
+//!
+//! The idle thread runs whenever nothing else is runnable.
+//! It is never placed in the schedule; instead, context
+//! switches fall back to it when the schedule is empty,
+//! and the timer handler switches away from it as soon
+//! as another thread becomes runnable.
+//!
+//! Not part of the original C implementation.
+
+use core::arch::asm;
+use core::ffi::c_void;
+use core::ptr::{self, NonNull, null_mut};
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use super::manager::allocateThread;
+use super::ThreadBlock;
+
+/// The idle thread, once created.
+static idleThread: AtomicPtr<ThreadBlock> = AtomicPtr::new(null_mut());
+
+/// Number of ticks spent in the idle thread.
+static idleTicks: AtomicU32 = AtomicU32::new(0);
+
+
+/// Create the idle thread.
+///
+/// Must be called once, before the first context switch
+/// can find the schedule empty.
+pub fn initIdleThread() -> Result<(), ()> {
+    let thread = allocateThread().ok_or(())?;
+    thread.loadKernel(idle, null_mut());
+
+    idleThread.store(ptr::from_ref(thread.get_ref()).cast_mut(), Ordering::Release);
+    Ok(())
+}
+
+/// Obtain the idle thread.
+pub(super) fn getIdleThread<'a>() -> Option<&'a ThreadBlock> {
+    NonNull::new(idleThread.load(Ordering::Acquire)).map(|p| unsafe { p.as_ref() })
+}
+
+/// Whether a thread is the idle thread.
+pub(super) fn isIdleThread(thread: &ThreadBlock) -> bool {
+    ptr::eq(idleThread.load(Ordering::Acquire), thread)
+}
+
+/// Record a tick spent idling.
+pub(super) fn chargeIdleTick() {
+    idleTicks.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Get the number of timer ticks spent idling.
+///
+/// The count wraps around once it overflows.
+pub fn getIdleTicks() -> u32 {
+    idleTicks.load(Ordering::Acquire)
+}
+
+/// Body of the idle thread.
+///
+/// sti only takes effect after the following instruction,
+/// so no interrupt can be taken between it and hlt,
+/// and a wakeup cannot be missed before halting.
+unsafe extern "cdecl" fn idle(_: *mut c_void) -> ! {
+    loop {
+        unsafe { asm!("sti", "hlt", options(att_syntax, nomem, nostack)); }
+    }
+}
@@ -0,0 +1,136 @@
+//! Join handles for kernel threads.
+//!
+//! The spawned thread and the handle share a packet
+//! holding the thread's result, which the thread fills
+//! in and signals on a cond var once its body returns.
+//! If the handle is dropped first, the thread is detached,
+//! and drops its result itself instead.
+//!
+//! Not part of the original C implementation.
+
+use alloc::sync::Arc;
+
+use crate::sync::cond::Cond;
+use crate::sync::mutex::Mutex;
+
+use super::kernel_thread::spawn_kernel_thread;
+use super::signal::Interrupted;
+use super::{ThreadBlock, ThreadHandle};
+
+/// Result shared between a kernel thread and its join handle.
+///
+/// Contains:
+///   result: The value returned by the thread, once it has finished.
+///   finished: Signaled when result is filled in.
+#[derive(Debug)]
+struct JoinPacket<T> {
+    result: Mutex<JoinState<T>>,
+    finished: Cond
+}
+
+#[derive(Debug)]
+enum JoinState<T> {
+    Running,
+    Finished(T),
+    Joined,
+    Detached
+}
+
+/// An owned permission to wait on a kernel thread
+/// and receive its result.
+///
+/// Dropping the handle detaches the thread.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    packet: Arc<JoinPacket<T>>,
+    thread: ThreadHandle
+}
+
+
+/// Create and schedule a kernel thread whose
+/// result can be waited on.
+///
+/// Returns a handle to join the thread, or Err
+/// if the thread or its packet could not be allocated.
+pub fn spawn_joinable_kernel_thread<F, T>(f: F) -> Result<JoinHandle<T>, ()>
+where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    let packet = Arc::try_new(JoinPacket {
+        result: Mutex::new(JoinState::Running),
+        finished: Cond::new()
+    }).map_err(|_| ())?;
+
+    let theirPacket = Arc::clone(&packet);
+    let thread = spawn_kernel_thread(move || {
+        let value = f();
+
+        let mut result = theirPacket.result.lock();
+
+        if matches!(*result, JoinState::Detached) {
+            // No one will ever take the value.
+            drop(result);
+            drop(value);
+        } else {
+            *result = JoinState::Finished(value);
+            theirPacket.finished.broadcastCond();
+        }
+    })?;
+
+    Ok(JoinHandle { packet, thread })
+}
+
+impl<T> JoinHandle<T> {
+    /// The thread this handle joins.
+    pub fn thread(&self) -> &ThreadBlock {
+        &self.thread
+    }
+
+    /// Whether the thread's body has returned.
+    pub fn is_finished(&self) -> bool {
+        matches!(*self.packet.result.lock(), JoinState::Finished(_))
+    }
+
+    /// Wait for the thread's body to return, and take its result.
+    ///
+    /// If the calling thread is sent a signal first,
+    /// returns Err and the thread is detached.
+    pub fn join(self) -> Result<T, Interrupted> {
+        let mut result = self.packet.result.lock();
+
+        loop {
+            match core::mem::replace(&mut *result, JoinState::Joined) {
+                JoinState::Finished(value) => return Ok(value),
+                JoinState::Running => {
+                    *result = JoinState::Running;
+                    result = self.packet.finished.waitForCond(result)?;
+                },
+                JoinState::Joined | JoinState::Detached => unreachable!("thread joined twice")
+            }
+        }
+    }
+
+    /// Let the thread run to completion unobserved.
+    ///
+    /// Same as dropping the handle.
+    pub fn detach(self) {
+        drop(self);
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    /// Detach the thread, unless it has been joined.
+    ///
+    /// A result the thread already produced is dropped now,
+    /// and one it produces later is dropped by the thread,
+    /// rather than kept in the packet with no one to take it.
+    /// Once the thread exits, the reaper releases its kernel
+    /// stack as for any other thread.
+    fn drop(&mut self) {
+        let mut result = self.packet.result.lock();
+
+        if !matches!(*result, JoinState::Joined) {
+            let unclaimed = core::mem::replace(&mut *result, JoinState::Detached);
+            drop(result);
+            drop(unclaimed);
+        }
+    }
+}
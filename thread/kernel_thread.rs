@@ -0,0 +1,74 @@
+//! Threads that run only in the kernel.
+//!
+//! Kernel threads have no task and never enter user mode.
+//! Instead of returning through exitKernelMode,
+//! their initial state returns into a trampoline that
+//! runs a Rust closure and then exits the thread.
+//!
+//! Not part of the original C implementation.
+
+use core::arch::asm;
+use core::ffi::c_void;
+
+use alloc::boxed::Box;
+
+use crate::sync::disable_interrupts::disableInterrupts;
+use crate::virtual_memory::useKernelDirectory;
+
+use super::manager::{allocateThread, discardThread, exitThread};
+use super::scheduler::scheduleThread;
+use super::ThreadHandle;
+
+/// The closure run by a kernel thread.
+///
+/// Boxed a second time when passed to the trampoline,
+/// so that it fits in a thin pointer.
+type KernelThreadBody = Box<dyn FnOnce() + Send>;
+
+
+/// Create and schedule a thread that runs f in the kernel.
+///
+/// The thread exits once f returns.
+///
+/// Returns a handle to the new thread, or Err
+/// if the thread or the closure could not be allocated.
+pub fn spawn_kernel_thread<F>(f: F) -> Result<ThreadHandle, ()>
+where F: FnOnce() + Send + 'static {
+    let body: KernelThreadBody = Box::try_new(f).map_err(|_| ())?;
+    let body = Box::try_new(body).map_err(|_| ())?;
+
+    let Some(thread) = allocateThread()
+    else { return Err(()); };
+
+    let body = Box::into_raw(body);
+    thread.inKernelDirectory.set(true);
+    thread.loadKernel(kernelThreadTrampoline, body.cast());
+
+    let handle = thread.handle();
+    if scheduleThread(&disableInterrupts(), &handle).is_err() {
+        // The thread never ran, so nothing else refers to it or its body.
+        drop(handle);
+        discardThread(thread);
+        drop(unsafe { Box::from_raw(body) });
+        return Err(());
+    }
+
+    Ok(handle)
+}
+
+/// Entry point of every kernel thread.
+///
+/// Threads are first continued with interrupts disabled,
+/// so they are re-enabled before running the body.
+unsafe extern "cdecl" fn kernelThreadTrampoline(arg: *mut c_void) -> ! {
+    let body = unsafe { Box::from_raw(arg.cast::<KernelThreadBody>()) };
+
+    unsafe {
+        useKernelDirectory();
+        asm!("sti", options(att_syntax, nomem, nostack));
+    }
+
+    body();
+
+    exitThread()
+}
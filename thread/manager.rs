@@ -1,6 +1,22 @@
+use core::alloc::Layout;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::alloc::{alloc, dealloc};
+
 use crate::registers::SuspendedState;
+use crate::sync::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+use crate::sync::irq_spinlock::{IrqSpinLock, IrqSpinLockGuard};
+use crate::variable_queue::Head;
 
-use super::{Thread, ThreadCollection, ThreadHandle, getCurrentThread};
+use super::context_switch::yieldThreadWithoutInterrupts;
+use super::scheduler::descheduleThread;
+use super::stats::ThreadStatsSnapshot;
+use super::thread_internal::KERNEL_STACK_SIZE;
+use super::tid_index::{assignTid, removeTid};
+use super::{Thread, ThreadBlock, ThreadCollection, ThreadQueue, getCurrentThread};
 
 /// Threads that are in use.
 static activeColl: ThreadCollection = ThreadCollection::new();
@@ -9,27 +25,220 @@ static activeColl: ThreadCollection = ThreadCollection::new();
 /// do not correspond to a thread.
 static freeColl: ThreadCollection = ThreadCollection::new();
 
+/// Number of thread blocks currently in freeColl.
+static freeCount: AtomicUsize = AtomicUsize::new(0);
 
-// Kernel functions
+/// Maximum number of thread blocks kept in freeColl for reuse.
+///
+/// Any further reaped threads are returned to the allocator.
+const MAX_FREE_THREADS: usize = 16;
+
+/// Threads that have exited and been switched away from,
+/// but whose kernel stacks have not been released yet.
+///
+/// Threads are added to this queue during context switches,
+/// so like the schedule it is only locked with interrupts disabled.
+///
+/// Not part of the original C implementation.
+static deadQueue: IrqSpinLock<ThreadQueue> = IrqSpinLock::new(Head::new());
+
+
+impl Thread {
+    /// Layout of a full thread allocation, including the kernel stack.
+    const LAYOUT: Layout = unsafe {
+        Layout::from_size_align_unchecked(KERNEL_STACK_SIZE, align_of::<ThreadBlock>())
+    };
+
+    /// Allocate a new thread block along with its kernel stack.
+    fn alloc() -> Option<Pin<Thread>> {
+        unsafe {
+            let block = NonNull::new(alloc(Thread::LAYOUT).cast::<ThreadBlock>())?;
+            block.write(ThreadBlock::new());
+            Some(Pin::new_unchecked(Thread(block.as_ptr())))
+        }
+    }
+
+    /// Take ownership of a thread allocation from a raw pointer.
+    pub(super) unsafe fn from_raw(thread: *const ThreadBlock) -> Thread {
+        Thread(thread.cast_mut())
+    }
+
+    /// Give up ownership of a thread allocation without freeing it.
+    pub(super) fn into_raw(self) -> *mut ThreadBlock {
+        ManuallyDrop::new(self).0
+    }
+}
 
 impl Drop for Thread {
-    /// Frees an active thread.
+    /// Returns a thread allocation, including the kernel stack,
+    /// to the allocator.
     fn drop(&mut self) {
-        activeColl.removeThread(self)
+        unsafe {
+            ptr::drop_in_place(self.0);
+            dealloc(self.0.cast(), Thread::LAYOUT);
+        }
+    }
+}
+
+
+// Kernel functions
+
+/// Obtain a thread block for a new thread and make it active.
+///
+/// Exited threads are reaped first so that their blocks
+/// can be reused; a new allocation is only made if
+/// freeColl is empty.
+/// The new thread is assigned a tid.
+pub(super) fn allocateThread() -> Option<Pin<&'static ThreadBlock>> {
+    reapThreads();
+
+    let mut thread = match freeColl.popThread() {
+        Some(mut thread) => {
+            freeCount.fetch_sub(1, Ordering::AcqRel);
+
+            // Weak handles to the previous thread may still be around,
+            // so the block is reset in place rather than overwritten.
+            thread.as_mut().recycle();
+            thread
+        },
+        None => Thread::alloc()?
+    };
+
+    assignTid(&disableInterrupts(), thread.as_mut());
+
+    Some(activeColl.insertThread(thread))
+}
+
+/// Give back a thread from allocateThread that never ran.
+///
+/// Not part of the original C implementation.
+pub(super) fn discardThread(thread: Pin<&ThreadBlock>) {
+    let thread = activeColl.removeThread(thread);
+    removeTid(&disableInterrupts(), &thread);
+
+    // Invalidate any weak handles to this thread.
+    thread.generation.fetch_add(1, Ordering::AcqRel);
+
+    releaseThread(unsafe { Pin::into_inner_unchecked(thread) });
+}
+
+/// Terminate the current thread.
+///
+/// A thread cannot free the kernel stack it is running on,
+/// so it is only removed from the active threads and descheduled.
+/// The context switch away from it then moves it to the dead queue,
+/// from which reapThreads releases it.
+///
+/// Not part of the original C implementation, which freed
+/// thread blocks directly in vanish.
+pub fn exitThread() -> ! {
+    let thread = getCurrentThread().expect("exitThread called outside of a thread");
+
+    // Ownership passes to the dead queue once we have switched away.
+    // Removing may block, so it is done while we can still be preempted.
+    let _ = ManuallyDrop::new(activeColl.removeThread(unsafe { Pin::new_unchecked(thread) }));
+
+    // A preemption sends an exited thread to the dead queue,
+    // so we must be out of the schedule before we are marked exited.
+    let disabledInterrupts = disableInterrupts();
+    removeTid(&disabledInterrupts, thread);
+    let _ = descheduleThread(&disabledInterrupts, &thread.handle());
+
+    thread.exited.set(true);
+
+    // Invalidate any weak handles to this thread.
+    thread.generation.fetch_add(1, Ordering::AcqRel);
+
+    // We will never be rescheduled, so this only returns
+    // if there was nothing else to run.
+    loop {
+        let _ = yieldThreadWithoutInterrupts(&disabledInterrupts, None);
+    }
+}
+
+fn getDeadQueue(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<ThreadQueue> {
+    deadQueue.lock()
+}
+
+/// Hand an exited thread to the dead queue.
+///
+/// Called by continueThread once it has switched away from
+/// the exited thread, so that it will never run again.
+///
+/// Must be called while interrupts are disabled.
+pub(super) fn queueDeadThread(disabledInterrupts: &DisabledInterruptsGuard, thread: Thread) {
+    let mut dead = getDeadQueue(disabledInterrupts);
+    let thread = unsafe { Pin::new_unchecked(&*thread.into_raw()) };
+    unsafe { insert_tail!(&mut dead, thread, link); }
+}
+
+/// Take the oldest thread off the dead queue.
+fn popDeadThread() -> Option<Thread> {
+    let disabledInterrupts = disableInterrupts();
+    let mut dead = getDeadQueue(&disabledInterrupts);
+
+    let thread = dead.front_ptr()?;
+    remove!(&mut dead, unsafe { &*thread }, link);
+    Some(unsafe { Thread::from_raw(thread) })
+}
+
+/// Release a thread block that is no longer running.
+///
+/// Blocks are recycled through freeColl to avoid allocator churn,
+/// unless enough free blocks are already cached.
+/// Blocks with outstanding weak handles are always kept,
+/// as those handles still need to read the generation.
+fn releaseThread(thread: Thread) {
+    if freeCount.fetch_add(1, Ordering::AcqRel) < MAX_FREE_THREADS
+        || thread.weakCount.load(Ordering::Acquire) != 0
+    {
+        thread.free.set(true);
+        freeColl.insertThread(unsafe { Pin::new_unchecked(thread) });
+    } else {
+        freeCount.fetch_sub(1, Ordering::AcqRel);
+        drop(thread);
     }
 }
 
-/// Obtain an active thread block corresponding to a tid.
-pub fn getActiveThreadByTid(tid: i32) -> Option<ThreadHandle> {
-    let coll = activeColl.queue.lock();
+/// Release the kernel stacks of exited threads.
+///
+/// Threads still referenced by a handle are left on the dead queue
+/// to be reaped on a later pass.
+///
+/// May block, so must not be called with interrupts disabled.
+pub fn reapThreads() {
+    let count = getDeadQueue(&disableInterrupts()).iter(|t| &t.link).count();
 
-    for curr in coll.iter(|t| t.link) {
-        if curr.tid == tid {
-            return Some(curr.handle())
+    for _ in 0..count {
+        let Some(thread) = popDeadThread()
+        else { break; };
+
+        if thread.refCount.load(Ordering::Acquire) != 0 {
+            queueDeadThread(&disableInterrupts(), thread);
+        } else {
+            releaseThread(thread);
         }
     }
+}
+
+/// Take a snapshot of the statistics of every active thread.
+///
+/// Fills out with as many snapshots as fit, and returns
+/// the number of active threads, which may be larger.
+///
+/// Not part of the original C implementation.
+pub fn snapshotThreadStats(out: &mut [ThreadStatsSnapshot]) -> usize {
+    let mut count = 0;
+
+    activeColl.forEachThread(|thread| {
+        if let Some(slot) = out.get_mut(count) {
+            *slot = thread.stats();
+        }
+
+        count += 1;
+    });
 
-    None
+    count
 }
 
 /// Sets the suspended user state pointer
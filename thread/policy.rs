@@ -0,0 +1,170 @@
+//! Scheduling policies.
+//!
+//! The schedule itself only handles locking and the
+//! scheduled flag on each thread; which thread runs next
+//! is decided by a SchedulerPolicy.
+//! The policy in use is chosen at build time through
+//! the sched-* features, defaulting to priority scheduling.
+//! At most one of those features may be enabled.
+//!
+//! Not part of the original C implementation,
+//! which only had a round-robin schedule.
+
+mod round_robin;
+mod priority;
+mod lottery;
+mod stride;
+
+use super::{ThreadBlock, ThreadHandle};
+
+pub use round_robin::RoundRobin;
+pub use priority::Priority;
+pub use lottery::Lottery;
+pub use stride::Stride;
+
+/// A strategy for ordering scheduled threads.
+///
+/// Every method is called with the schedule locked
+/// and interrupts disabled, so implementations
+/// must not block.
+pub trait SchedulerPolicy {
+    /// An empty schedule.
+    const EMPTY: Self;
+
+    /// Add a thread that has just become scheduled.
+    fn enqueue(&mut self, thread: &ThreadBlock);
+
+    /// Remove a thread that is no longer scheduled.
+    fn dequeue(&mut self, thread: &ThreadBlock);
+
+    /// Choose the next thread to run.
+    ///
+    /// The chosen thread stays enqueued.
+    fn pick_next(&mut self) -> Option<ThreadHandle>;
+
+    /// Account a timer tick to the running thread.
+    ///
+    /// Returns whether a thread the policy prefers
+    /// over the running one is waiting to run.
+    /// Quantum expiry is handled by the timer itself,
+    /// so policies without such a preference return false.
+    fn tick(&mut self, current: &ThreadBlock) -> bool;
+
+    /// Update the policy for a thread being run directly,
+    /// bypassing pick_next.
+    ///
+    /// Fails if the thread is not enqueued.
+    fn yield_to(&mut self, thread: &ThreadBlock) -> Result<(), ()>;
+
+    /// Update the policy after the priority of an enqueued thread changed.
+    fn reprioritize(&mut self, thread: &ThreadBlock) {
+        self.dequeue(thread);
+        self.enqueue(thread);
+    }
+}
+
+#[cfg(any(
+    all(feature = "sched-round-robin", feature = "sched-lottery"),
+    all(feature = "sched-round-robin", feature = "sched-stride"),
+    all(feature = "sched-lottery", feature = "sched-stride")
+))]
+compile_error!("only one of the sched-round-robin, sched-lottery and sched-stride features may be enabled");
+
+#[cfg(feature = "sched-round-robin")]
+pub type ActivePolicy = RoundRobin;
+
+#[cfg(feature = "sched-lottery")]
+pub type ActivePolicy = Lottery;
+
+#[cfg(feature = "sched-stride")]
+pub type ActivePolicy = Stride;
+
+#[cfg(not(any(feature = "sched-round-robin", feature = "sched-lottery", feature = "sched-stride")))]
+pub type ActivePolicy = Priority;
+
+/// Number of lottery tickets, or inverse stride, for a priority.
+///
+/// More important priorities get proportionally more.
+#[inline(always)]
+pub(super) fn ticketsFor(thread: &ThreadBlock) -> u32 {
+    (super::scheduler::NUM_PRIORITIES - thread.effectivePriority() as usize) as u32
+}
+
+/// The kernel crate cannot run tests,
+/// so these are run by host_tests with cargo test.
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+
+    /// Number of picks made in each comparison.
+    const PICKS: usize = 600;
+
+    /// Schedule a thread at each of the given priorities,
+    /// and count how often each is picked.
+    fn picks<P: SchedulerPolicy, const N: usize>(priorities: [u8; N]) -> [usize; N] {
+        let threads = priorities.map(|priority| {
+            let thread = ThreadBlock::new();
+            thread.priority.set(priority);
+            thread
+        });
+
+        let mut policy = P::EMPTY;
+        for thread in &threads {
+            policy.enqueue(thread);
+        }
+
+        let mut counts = [0; N];
+        for _ in 0..PICKS {
+            let picked = policy.pick_next().expect("schedule emptied by pick_next");
+            let i = threads.iter().position(|t| ptr::eq(t, &*picked)).unwrap();
+            counts[i] += 1;
+        }
+
+        for thread in &threads {
+            policy.dequeue(thread);
+        }
+
+        counts
+    }
+
+    #[test]
+    fn round_robin_ignores_priority() {
+        assert_eq!(picks::<RoundRobin, 3>([0, 16, 31]), [200, 200, 200]);
+    }
+
+    #[test]
+    fn priority_prefers_important_threads() {
+        let counts = picks::<Priority, 2>([4, 20]);
+        assert!(counts[0] > counts[1], "{counts:?}");
+    }
+
+    #[test]
+    fn lottery_shares_by_tickets() {
+        // 28 tickets against 12.
+        let counts = picks::<Lottery, 2>([4, 20]);
+        assert!(counts[0] > counts[1], "{counts:?}");
+    }
+
+    #[test]
+    fn stride_shares_exactly_by_tickets() {
+        // 32 tickets against 16.
+        let counts = picks::<Stride, 2>([0, 16]);
+        assert!(counts[0].abs_diff(2 * counts[1]) <= 2, "{counts:?}");
+    }
+
+    #[test]
+    fn no_policy_starves_a_thread() {
+        let priorities = [0, 16, 31];
+
+        for counts in [
+            picks::<RoundRobin, 3>(priorities),
+            picks::<Priority, 3>(priorities),
+            picks::<Lottery, 3>(priorities),
+            picks::<Stride, 3>(priorities)
+        ] {
+            assert!(counts.iter().all(|&n| n > 0), "{counts:?}");
+        }
+    }
+}
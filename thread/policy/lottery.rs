@@ -0,0 +1,82 @@
+//! Lottery scheduling.
+
+use core::pin::Pin;
+
+use crate::variable_queue::Head;
+
+use super::super::{ThreadBlock, ThreadHandle, ThreadQueue};
+use super::{SchedulerPolicy, ticketsFor};
+
+/// Each thread holds tickets according to its priority,
+/// and each pick draws a random ticket.
+pub struct Lottery {
+    queue: ThreadQueue,
+    totalTickets: u32,
+    /// State of the xorshift generator used for draws.
+    seed: u32
+}
+
+impl Lottery {
+    /// Draw a number below bound.
+    fn draw(&mut self, bound: u32) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed % bound
+    }
+}
+
+impl SchedulerPolicy for Lottery {
+    const EMPTY: Lottery = Lottery {
+        queue: Head::new(),
+        totalTickets: 0,
+        seed: 0x410
+    };
+
+    fn enqueue(&mut self, thread: &ThreadBlock) {
+        if !thread.scheduleLink.in_queue() {
+            self.totalTickets += ticketsFor(thread);
+            unsafe { insert_tail!(&mut self.queue, Pin::new_unchecked(thread), scheduleLink); }
+        }
+    }
+
+    fn dequeue(&mut self, thread: &ThreadBlock) {
+        if thread.scheduleLink.in_queue() {
+            self.totalTickets -= ticketsFor(thread);
+            remove!(&mut self.queue, thread, scheduleLink);
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadHandle> {
+        if self.totalTickets == 0 {
+            return None;
+        }
+
+        let mut winner = self.draw(self.totalTickets);
+        for thread in self.queue.iter(|t| &t.scheduleLink) {
+            let tickets = ticketsFor(thread);
+            if winner < tickets {
+                return Some(thread.handle());
+            }
+            winner -= tickets;
+        }
+
+        None
+    }
+
+    /// Each draw is independent, so there is never a thread
+    /// to prefer before the quantum, which the timer handles, expires.
+    fn tick(&mut self, _current: &ThreadBlock) -> bool {
+        false
+    }
+
+    fn yield_to(&mut self, thread: &ThreadBlock) -> Result<(), ()> {
+        if thread.scheduleLink.in_queue() { Ok(()) } else { Err(()) }
+    }
+
+    fn reprioritize(&mut self, thread: &ThreadBlock) {
+        // Tickets are derived from the priority, which has already changed,
+        // so the total is simply recounted.
+        self.totalTickets = self.queue.iter(|t| &t.scheduleLink).map(ticketsFor).sum();
+    }
+}
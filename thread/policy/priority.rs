@@ -0,0 +1,139 @@
+//! Priority scheduling with aging.
+
+use core::pin::Pin;
+
+use crate::variable_queue::Head;
+
+use super::super::scheduler::NUM_PRIORITIES;
+use super::super::{ThreadBlock, ThreadHandle, ThreadQueue};
+use super::SchedulerPolicy;
+
+/// Number of times a level may be passed over
+/// before its first thread is promoted a level.
+const AGING_THRESHOLD: u32 = 8;
+
+/// A round-robin queue per priority level,
+/// with a bitmap of which levels are non-empty so that
+/// the most important level can be found immediately.
+pub struct Priority {
+    /// One queue for each priority level.
+    queues: [ThreadQueue; NUM_PRIORITIES],
+    /// Bit i is set when queues[i] is non-empty.
+    bitmap: u32,
+    /// How many times each level has been passed over
+    /// since it last ran or was promoted.
+    passedOver: [u32; NUM_PRIORITIES]
+}
+
+impl Priority {
+    /// Add a thread to the front of a level.
+    fn insert(&mut self, thread: &ThreadBlock, level: u8) {
+        thread.schedLevel.set(level);
+        unsafe {
+            insert_front!(&mut self.queues[level as usize], Pin::new_unchecked(thread), scheduleLink);
+        }
+        self.bitmap |= 1 << level;
+    }
+
+    /// Add a thread to the back of a level.
+    fn insertTail(&mut self, thread: &ThreadBlock, level: u8) {
+        thread.schedLevel.set(level);
+        unsafe {
+            insert_tail!(&mut self.queues[level as usize], Pin::new_unchecked(thread), scheduleLink);
+        }
+        self.bitmap |= 1 << level;
+    }
+
+    /// Remove a thread from whichever level it is in.
+    fn remove(&mut self, thread: &ThreadBlock) {
+        let level = thread.schedLevel.get() as usize;
+        remove!(&mut self.queues[level], thread, scheduleLink);
+
+        if self.queues[level].front().is_none() {
+            self.bitmap &= !(1 << level);
+            self.passedOver[level] = 0;
+        }
+    }
+
+    /// Age every level less important than the one that just ran.
+    ///
+    /// Once a level has waited long enough its first
+    /// thread is promoted a level, so it cannot be starved.
+    fn age(&mut self, ran: usize) {
+        let mut starving = self.bitmap & u32::MAX.checked_shl(ran as u32 + 1).unwrap_or(0);
+
+        while starving != 0 {
+            let level = starving.trailing_zeros() as usize;
+            starving &= starving - 1;
+
+            self.passedOver[level] += 1;
+            if self.passedOver[level] >= AGING_THRESHOLD {
+                self.passedOver[level] = 0;
+
+                let aged = self.queues[level].front_ptr().map(|t| unsafe { &*t });
+                if let Some(aged) = aged {
+                    self.remove(aged);
+                    self.insertTail(aged, level as u8 - 1);
+                }
+            }
+        }
+    }
+}
+
+impl SchedulerPolicy for Priority {
+    const EMPTY: Priority = Priority {
+        queues: [const { Head::new() }; NUM_PRIORITIES],
+        bitmap: 0,
+        passedOver: [0; NUM_PRIORITIES]
+    };
+
+    /// New threads run next among those of their priority.
+    fn enqueue(&mut self, thread: &ThreadBlock) {
+        self.insert(thread, thread.effectivePriority());
+    }
+
+    fn dequeue(&mut self, thread: &ThreadBlock) {
+        self.remove(thread);
+    }
+
+    /// Take the first thread of the most important level.
+    fn pick_next(&mut self) -> Option<ThreadHandle> {
+        if self.bitmap == 0 {
+            return None;
+        }
+
+        let level = self.bitmap.trailing_zeros() as usize;
+        let thread = self.queues[level].front()?.handle();
+        self.yield_to(&thread).ok()?;
+
+        Some(thread)
+    }
+
+    /// Preempt if a more important level has threads waiting.
+    fn tick(&mut self, current: &ThreadBlock) -> bool {
+        current.scheduleLink.in_queue()
+            && self.bitmap & ((1 << current.schedLevel.get()) - 1) != 0
+    }
+
+    /// Rotate the thread to the back of its base level,
+    /// using up any boost from aging.
+    fn yield_to(&mut self, thread: &ThreadBlock) -> Result<(), ()> {
+        if !thread.scheduleLink.in_queue() {
+            return Err(());
+        }
+
+        let level = thread.schedLevel.get() as usize;
+        self.passedOver[level] = 0;
+
+        self.remove(thread);
+        self.insertTail(thread, thread.effectivePriority());
+        self.age(level);
+
+        Ok(())
+    }
+
+    fn reprioritize(&mut self, thread: &ThreadBlock) {
+        self.remove(thread);
+        self.insertTail(thread, thread.effectivePriority());
+    }
+}
@@ -0,0 +1,52 @@
+//! Round-robin scheduling, as in the original C implementation.
+
+use core::pin::Pin;
+
+use crate::variable_queue::Head;
+
+use super::super::{ThreadBlock, ThreadHandle, ThreadQueue};
+use super::SchedulerPolicy;
+
+/// A single queue, run in order.
+///
+/// Newly scheduled threads are placed at the front,
+/// and each thread picked moves to the back.
+pub struct RoundRobin {
+    queue: ThreadQueue
+}
+
+impl SchedulerPolicy for RoundRobin {
+    const EMPTY: RoundRobin = RoundRobin { queue: Head::new() };
+
+    fn enqueue(&mut self, thread: &ThreadBlock) {
+        unsafe { insert_front!(&mut self.queue, Pin::new_unchecked(thread), scheduleLink); }
+    }
+
+    fn dequeue(&mut self, thread: &ThreadBlock) {
+        remove!(&mut self.queue, thread, scheduleLink);
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadHandle> {
+        let thread = self.queue.front()?.handle();
+        self.yield_to(&thread).ok()?;
+        Some(thread)
+    }
+
+    /// Every thread is equal, so only quantum expiry,
+    /// which the timer handles, preempts the running thread.
+    fn tick(&mut self, _current: &ThreadBlock) -> bool {
+        false
+    }
+
+    fn yield_to(&mut self, thread: &ThreadBlock) -> Result<(), ()> {
+        if !thread.scheduleLink.in_queue() {
+            return Err(());
+        }
+
+        remove!(&mut self.queue, thread, scheduleLink);
+        unsafe { insert_tail!(&mut self.queue, Pin::new_unchecked(thread), scheduleLink); }
+        Ok(())
+    }
+
+    fn reprioritize(&mut self, thread: &ThreadBlock) {}
+}
@@ -0,0 +1,70 @@
+//! Stride scheduling.
+
+use core::pin::Pin;
+
+use crate::variable_queue::Head;
+
+use super::super::{ThreadBlock, ThreadHandle, ThreadQueue};
+use super::{SchedulerPolicy, ticketsFor};
+
+/// Numerator used to turn tickets into a stride.
+const STRIDE1: u64 = 1 << 20;
+
+/// Each thread advances its pass by a stride inversely
+/// proportional to its tickets whenever it is picked,
+/// and the thread with the lowest pass runs next.
+pub struct Stride {
+    queue: ThreadQueue,
+    /// Pass of the most recently picked thread,
+    /// which newly scheduled threads start from.
+    globalPass: u64
+}
+
+impl Stride {
+    /// Charge a thread for being given the processor.
+    fn charge(&mut self, thread: &ThreadBlock) {
+        thread.pass.set(thread.pass.get() + STRIDE1 / ticketsFor(thread) as u64);
+    }
+}
+
+impl SchedulerPolicy for Stride {
+    const EMPTY: Stride = Stride {
+        queue: Head::new(),
+        globalPass: 0
+    };
+
+    fn enqueue(&mut self, thread: &ThreadBlock) {
+        // A thread that was blocked must not be able
+        // to catch up on the time it was not runnable.
+        thread.pass.set(thread.pass.get().max(self.globalPass));
+        unsafe { insert_tail!(&mut self.queue, Pin::new_unchecked(thread), scheduleLink); }
+    }
+
+    fn dequeue(&mut self, thread: &ThreadBlock) {
+        remove!(&mut self.queue, thread, scheduleLink);
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadHandle> {
+        let thread = self.queue.iter(|t| &t.scheduleLink).min_by_key(|t| t.pass.get())?.handle();
+        self.yield_to(&thread).ok()?;
+        Some(thread)
+    }
+
+    /// The running thread was charged its stride when picked,
+    /// so the rest of its quantum, which the timer ends, is already paid for.
+    fn tick(&mut self, _current: &ThreadBlock) -> bool {
+        false
+    }
+
+    fn yield_to(&mut self, thread: &ThreadBlock) -> Result<(), ()> {
+        if !thread.scheduleLink.in_queue() {
+            return Err(());
+        }
+
+        self.globalPass = thread.pass.get();
+        self.charge(thread);
+        Ok(())
+    }
+
+    fn reprioritize(&mut self, thread: &ThreadBlock) {}
+}
@@ -8,44 +8,65 @@
 //! We thus use disable_interrupts
 //! to prevent the timer from running.
 
-use core::ptr::NonNull;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 
 use crate::sync::disable_interrupts::{self, DisabledInterruptsGuard, disableInterrupts};
-use crate::sync::owned_lock::{OwnedLock, OwnedLockGuard};
+use crate::sync::irq_spinlock::{IrqSpinLock, IrqSpinLockGuard};
 use crate::sync::mutex::Mutex;
 use crate::variable_queue::Head;
+use crate::virtual_memory::{LOGIC_NULL, isUserReadableAddr};
 
-use super::context_switch::yieldThreadWithoutInterrupts;
-use super::{ThreadBlock, ThreadHandle, getCurrentThread};
+use super::context_switch::{yieldThread, yieldThreadTo, yieldThreadWithoutInterrupts};
+use super::policy::{ActivePolicy, SchedulerPolicy};
+use super::signal::{Interrupted, takeInterrupt};
+use super::{ThreadBlock, ThreadHandle, ThreadQueue, getCurrentThread};
 use super::thread_internal::getActiveThreadByTid;
 
-type ScheduledThreads = Head<ThreadBlock>;
+/// Number of priority levels.
+///
+/// Level 0 is the most important.
+pub const NUM_PRIORITIES: usize = 32;
 
+/// Priority given to new threads.
+pub const DEFAULT_PRIORITY: u8 = 16;
 
-/// Holds scheduling data, and a lock for synchronization
-struct Schedule(OwnedLock<ScheduleInner>);
+/// Inherited priority of a thread that has not inherited any.
+pub const NO_INHERITED_PRIORITY: u8 = u8::MAX;
 
-struct ScheduleInner {
-    next: Option<ThreadHandle>,
-    queue: ScheduledThreads
-}
+/// Maximum length of a chain of lock holders
+/// that a waiter's priority is passed along.
+const MAX_INHERITANCE_DEPTH: usize = 8;
+
+
+/// Holds scheduling data, and a lock for synchronization
+///
+/// How threads are ordered is left to the SchedulerPolicy
+/// selected at build time.
+struct Schedule(IrqSpinLock<ActivePolicy>);
 
 static sched: Schedule = Schedule::new();
 
+/// Threads waiting on a lock, through blockedOn.
+///
+/// Not part of the original C implementation.
+static blockedThreads: IrqSpinLock<ThreadQueue> = IrqSpinLock::new(Head::new());
+
 
 impl Schedule {
     /// Create a schedule.
     const fn new() -> Schedule {
-        Schedule(Mutex::new(ScheduleInner {
-            next: None,
-            queue: Head::new()
-        }))
+        Schedule(IrqSpinLock::new(ActivePolicy::EMPTY))
     }
 }
 
-fn getSchedule(_: &DisabledInterruptsGuard) -> OwnedLockGuard<ScheduleInner> {
-    sched.0.waitForLockWith(|t| {})
+fn getSchedule(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<ActivePolicy> {
+    sched.0.lock()
+}
+
+fn getBlockedThreads(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<ThreadQueue> {
+    blockedThreads.lock()
 }
 
 /// Move forward to the next thread in the schedule.
@@ -55,46 +76,36 @@ fn getSchedule(_: &DisabledInterruptsGuard) -> OwnedLockGuard<ScheduleInner> {
 ///
 /// Should only be run while interrupts are disabled.
 pub fn getNextThread(disabledInterrupts : &DisabledInterruptsGuard) -> Option<ThreadHandle> {
-    let sched_ = getSchedule(disabledInterrupts);
-
-    let curr = sched_.next;
-    let Some(curr) = curr
-    else {
-        sched_.next = sched_.queue.front().map(|t| t.handle());
-        return None;
-    };
+    getSchedule(disabledInterrupts).pick_next()
+}
 
-    let next = curr.scheduleLink.next();
-    match next {
-        None => { sched_.next = sched_.queue.front().map(|t| t.handle()); },
-        Some(next) => { sched_.next = Some(next); }
-    };
+/// Update the schedule for switching directly to a thread.
+///
+/// Fails if the thread is not scheduled.
+///
+/// Should only be run while interrupts are disabled.
+pub(super) fn yieldToThread(disabledInterrupts: &DisabledInterruptsGuard, thread: &ThreadBlock) -> Result<(), ()> {
+    getSchedule(disabledInterrupts).yield_to(thread)
+}
 
-    Some(curr)
+/// Account a timer tick to the running thread.
+///
+/// Returns whether the policy would rather run another thread.
+///
+/// Should only be run while interrupts are disabled.
+pub(super) fn tickSchedule(disabledInterrupts: &DisabledInterruptsGuard, current: &ThreadBlock) -> bool {
+    getSchedule(disabledInterrupts).tick(current)
 }
 
 /// Add a thread to the schedule.
 ///
-/// This will become the next scheduled thread
-/// to run.
+/// Depending on the policy, this may become
+/// the next scheduled thread to run.
 pub fn scheduleThread(disabledInterrupts : &DisabledInterruptsGuard, thread: &ThreadHandle) -> Result<(), ()> {
-    let sched_ = getSchedule(&disableInterrupts());
+    let mut sched_ = getSchedule(disabledInterrupts);
 
     if !thread.scheduled.swap(true, Ordering::AcqRel) {
-        match sched_.next {
-            None => {
-                unsafe {
-                    let thread = insert_tail!(&mut sched_.queue, thread.deref_pin(), scheduleLink);
-                }
-                sched_.next = Some(thread.handle());
-            },
-            Some(next) => {
-                unsafe {
-                    insert_after!(&mut sched_.queue, &next, thread.deref_pin(), scheduleLink);
-                }
-            }
-        }
-
+        sched_.enqueue(thread);
         Ok(())
     } else { Err(()) }
 }
@@ -113,46 +124,213 @@ pub fn scheduleThread(disabledInterrupts : &DisabledInterruptsGuard, thread: &Th
 /// have redundant context switches following
 /// descheduling.
 pub fn descheduleThread(disabledInterrupts: &DisabledInterruptsGuard, thread: &ThreadHandle) -> Result<(), ()> {
+    let mut sched_ = getSchedule(disabledInterrupts);
+
     if thread.scheduled.swap(false, Ordering::AcqRel) {
-        let sched_ = getSchedule(disabledInterrupts);
-        remove!(&mut sched_.queue, &thread, scheduleLink);
+        sched_.dequeue(thread);
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+impl ThreadBlock {
+    /// Get the base priority of a thread.
+    pub fn priority(&self) -> u8 {
+        self.priority.get()
+    }
 
-        if Some(thread) == sched_.next {
-            getNextThread(&disabledInterrupts);
+    /// Get the priority a thread is scheduled with.
+    ///
+    /// This is the more important of its base priority
+    /// and any priority inherited from threads waiting
+    /// on locks it holds.
+    ///
+    /// Not part of the original C implementation.
+    pub fn effectivePriority(&self) -> u8 {
+        self.priority.get().min(self.inheritedPriority.get())
+    }
+
+    /// Record that the thread is waiting on the lock
+    /// whose owner is stored in owner, or on nothing if null.
+    ///
+    /// Waiting threads are kept on the blocked queue,
+    /// so that holders can tell who is waiting on them.
+    ///
+    /// Not part of the original C implementation.
+    pub fn setBlockedOn(&self, owner: *const AtomicPtr<ThreadBlock>) {
+        let disabledInterrupts = disableInterrupts();
+        let mut blocked = getBlockedThreads(&disabledInterrupts);
+
+        self.blockedOn.set(owner);
+
+        if owner.is_null() {
+            if self.blockedLink.in_queue() {
+                remove!(&mut blocked, self, blockedLink);
+            }
+        } else if !self.blockedLink.in_queue() {
+            // Threads are pinned at the bottom of their kernel stacks.
+            unsafe { insert_tail!(&mut blocked, Pin::new_unchecked(self), blockedLink); }
+        }
+    }
+
+    /// Update the inherited priority after the thread released a lock.
+    ///
+    /// Releasing a lock can only take away priority,
+    /// so nothing needs doing unless some was inherited.
+    ///
+    /// Not part of the original C implementation.
+    pub fn lockReleased(&self) {
+        if self.inheritedPriority.get() != NO_INHERITED_PRIORITY {
+            self.recomputeInheritedPriority();
+        }
+    }
+
+    /// Set the inherited priority to that of the most important
+    /// thread still waiting on any lock this thread holds.
+    ///
+    /// Called on unlock, and on the receiving end of a handoff,
+    /// as the other waiters on the lock now wait on the receiver.
+    ///
+    /// Not part of the original C implementation.
+    pub fn recomputeInheritedPriority(&self) {
+        let disabledInterrupts = disableInterrupts();
+
+        let blocked = getBlockedThreads(&disabledInterrupts);
+        let inherited = blocked.iter(|t| &t.blockedLink)
+            .filter(|waiter| {
+                unsafe { waiter.blockedOn.get().as_ref() }
+                    .is_some_and(|owner| ptr::eq(owner.load(Ordering::Acquire), self))
+            })
+            .map(ThreadBlock::effectivePriority)
+            .min()
+            .unwrap_or(NO_INHERITED_PRIORITY);
+        drop(blocked);
+
+        if self.inheritedPriority.replace(inherited) == inherited {
+            return;
+        }
+
+        let mut sched_ = getSchedule(&disabledInterrupts);
+
+        if self.scheduleLink.in_queue() {
+            sched_.reprioritize(self);
+        }
+    }
+
+    /// Set the base priority of a thread.
+    ///
+    /// Lower values are more important.
+    /// How the priority is used depends on the scheduling policy.
+    ///
+    /// Not part of the original C implementation.
+    pub fn set_priority(&self, priority: u8) -> Result<(), ()> {
+        if priority as usize >= NUM_PRIORITIES {
+            return Err(());
+        }
+
+        let disabledInterrupts = disableInterrupts();
+        let mut sched_ = getSchedule(&disabledInterrupts);
+
+        self.priority.set(priority);
+        if self.scheduleLink.in_queue() {
+            sched_.reprioritize(self);
         }
 
         Ok(())
-    } else {
-        Err(())
     }
 }
 
-/// Obtain a scheduled thread block corresponding to a tid.
-pub fn getScheduledThreadByTid(tid: i32) -> Option<ThreadHandle> {
+/// Lend the current thread's priority to the holder
+/// of a lock it is waiting on, whose owner is stored in owner.
+///
+/// If the holder is itself waiting on a lock, the priority
+/// is passed on to that lock's holder, and so on,
+/// up to MAX_INHERITANCE_DEPTH holders.
+///
+/// Not part of the original C implementation.
+pub fn donatePriority(owner: &AtomicPtr<ThreadBlock>) {
+    let Some(donor) = getCurrentThread()
+    else { return; };
+
+    let priority = donor.effectivePriority();
+
+    // Holders cannot release their locks, or exit,
+    // while interrupts are disabled, so they are only
+    // looked up once they are.
     let disabledInterrupts = disableInterrupts();
+    let mut sched_ = getSchedule(&disabledInterrupts);
 
-    for curr in getSchedule(disabledInterrupts).queue.iter(|t| t.scheduleLink) {
-        if curr.tid == tid {
-            if curr.scheduled.get() {
-                Some(curr.handle())
-            } else {
-                None
-            }
+    let mut owner = ptr::from_ref(owner);
+
+    for _ in 0..MAX_INHERITANCE_DEPTH {
+        let Some(curr) = (unsafe { owner.as_ref() })
+            .and_then(|owner| unsafe { owner.load(Ordering::Acquire).as_ref() })
+        else { break; };
+
+        if ptr::eq(curr, donor) || priority >= curr.effectivePriority() {
+            break;
         }
+
+        curr.inheritedPriority.set(priority);
+        if curr.scheduleLink.in_queue() {
+            sched_.reprioritize(curr);
+        }
+
+        owner = curr.blockedOn.get();
     }
 }
 
-/// Blocks the thread until a condition is met.
+/// Obtain a scheduled thread block corresponding to a tid.
+pub fn getScheduledThreadByTid(tid: i32) -> Option<ThreadHandle> {
+    getActiveThreadByTid(tid).filter(|t| t.scheduled.load(Ordering::Acquire))
+}
+
+/// Blocks the thread until a condition is met
+/// or a signal is sent to it.
 ///
-/// If given NULL, will only deschedule until being rescheduled.
+/// The condition is checked first, so a wakeup racing
+/// with a signal is not lost.
+/// Returns Err if a signal cut the wait short,
+/// in which case the waiter must take itself off
+/// whatever wait list it is on.
 ///
 /// This function will only ever return with interrupts enabled.
-pub fn blockUntil(disabledInterrupts: &DisabledInterruptsGuard, cond: &AtomicBool) {
-    let thread = getCurrentThread()?;
+pub fn blockUntil(disabledInterrupts: &DisabledInterruptsGuard, cond: &AtomicBool) -> Result<(), Interrupted> {
+    let Some(thread) = getCurrentThread()
+    else { return Ok(()); };
 
-    while !cond.load(Ordering::Release) {
-        descheduleThread(&disabledInterrupts, thread);
-        yieldThreadWithoutInterrupts(&disabledInterrupts, None)
+    thread.interruptible.set(true);
+
+    let result = loop {
+        if cond.load(Ordering::Acquire) {
+            break Ok(());
+        } else if takeInterrupt(thread) {
+            break Err(Interrupted);
+        }
+
+        let _ = descheduleThread(disabledInterrupts, &thread.handle());
+        let _ = yieldThreadWithoutInterrupts(disabledInterrupts, None);
+    };
+
+    thread.interruptible.set(false);
+    result
+}
+
+/// Blocks the thread until a condition is met, ignoring signals.
+///
+/// Only for waits bounded by another thread's critical section,
+/// such as for a mutex, after which a killed thread
+/// soon returns to user mode and acts on its kill.
+///
+/// This function will only ever return with interrupts enabled.
+pub fn blockUntilUninterruptible(disabledInterrupts: &DisabledInterruptsGuard, cond: &AtomicBool) {
+    let Some(thread) = getCurrentThread()
+    else { return; };
+
+    while !cond.load(Ordering::Acquire) {
+        let _ = descheduleThread(disabledInterrupts, &thread.handle());
+        let _ = yieldThreadWithoutInterrupts(disabledInterrupts, None);
     }
 }
 
@@ -160,6 +338,61 @@ pub fn blockUntil(disabledInterrupts: &DisabledInterruptsGuard, cond: &AtomicBoo
 // Syscalls
 
 
+/// Deschedule the calling thread.
+///
+/// *reject is checked with interrupts disabled, and they stay
+/// disabled until we have switched away, so a make_runnable
+/// from another thread cannot slip in between the check
+/// and the descheduling and be lost.
+///
+/// # Parameters
+/// 1. reject: If the integer it points to is nonzero,
+///        the thread is not descheduled.
+///
+/// # Returns
+///
+/// 0 if *reject was nonzero,
+/// or once the thread has been made runnable again.
+/// -1 if reject is not a user-readable address.
+/// -2 if the thread was woken by a signal.
+pub fn deschedule(reject: *const i32) -> i32 {
+    let readable = unsafe {
+        isUserReadableAddr(LOGIC_NULL.offset(reject.addr()), size_of::<i32>())
+    };
+
+    if !readable || !reject.is_aligned() {
+        return -1;
+    }
+
+    let Some(thread) = getCurrentThread()
+    else { return -1; };
+
+    let disabledInterrupts = disableInterrupts();
+
+    if unsafe { reject.read_volatile() } != 0 {
+        return 0;
+    }
+
+    // Cleared by make_runnable, so spurious
+    // reschedules put us straight back to sleep.
+    thread.userDescheduled.store(true, Ordering::Release);
+    thread.interruptible.set(true);
+
+    while thread.userDescheduled.load(Ordering::Acquire) {
+        if takeInterrupt(thread) {
+            thread.userDescheduled.store(false, Ordering::Release);
+            thread.interruptible.set(false);
+            return -2;
+        }
+
+        let _ = descheduleThread(&disabledInterrupts, &thread.handle());
+        let _ = yieldThreadWithoutInterrupts(&disabledInterrupts, None);
+    }
+
+    thread.interruptible.set(false);
+    0
+}
+
 /// Schedule a thread.
 ///
 /// # Parameters
@@ -170,11 +403,54 @@ pub fn blockUntil(disabledInterrupts: &DisabledInterruptsGuard, cond: &AtomicBoo
 ///
 /// 0 if successfully scheduled,
 /// -1 otherwise.
-fn make_runnable(tid: i32) -> i32 {
+pub fn make_runnable(tid: i32) -> i32 {
     if tid < 0 {
         return -1;
     }
 
     let Some(thread) = getActiveThreadByTid(tid)
     else { return -1; };
+
+    let disabledInterrupts = disableInterrupts();
+
+    if !thread.userDescheduled.swap(false, Ordering::AcqRel) {
+        return -1;
+    }
+
+    // The thread may already be scheduled if it has not
+    // finished switching away yet, in which case clearing
+    // userDescheduled is enough to keep it running.
+    let _ = scheduleThread(&disabledInterrupts, &thread);
+
+    0
+}
+
+/// Defer execution to another thread.
+///
+/// # Parameters
+/// 1. tid: Tid of the thread to run next,
+///        or -1 to run whichever thread is next in the schedule.
+///
+/// # Returns
+///
+/// 0 once the calling thread runs again.
+/// -1 if no thread has the given tid.
+/// -2 if the thread is not runnable.
+pub fn r#yield(tid: i32) -> i32 {
+    if tid == -1 {
+        let _ = yieldThread(None);
+        return 0;
+    } else if tid < 0 {
+        return -1;
+    }
+
+    let Some(thread) = getActiveThreadByTid(tid)
+    else { return -1; };
+
+    let disabledInterrupts = disableInterrupts();
+
+    match yieldThreadTo(&disabledInterrupts, &thread) {
+        Ok(()) => 0,
+        Err(()) => -2
+    }
 }
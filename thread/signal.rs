@@ -0,0 +1,82 @@
+//! Kernel-mode signals.
+//!
+//! Each thread has a word of pending signals, which other
+//! threads set to ask it to stop what it is doing.
+//! Waits through blockUntil give up as soon as a signal is pending,
+//! taking the waiter off its wait list,
+//! and signals are acted on when the thread next returns
+//! to user mode, so a thread being killed does not stay
+//! asleep in a wait list forever.
+//! Only waits bounded by another thread's critical section,
+//! such as for a mutex, ignore signals.
+//!
+//! Not part of the original C implementation.
+
+use core::sync::atomic::Ordering;
+
+use crate::sync::disable_interrupts::disableInterrupts;
+
+use super::manager::exitThread;
+use super::scheduler::scheduleThread;
+use super::{ThreadBlock, getCurrentThread};
+
+/// Interrupt the thread's current wait.
+pub const SIGNAL_INTERRUPT: u32 = 1 << 0;
+
+/// Terminate the thread on its next return to user mode.
+pub const SIGNAL_KILL: u32 = 1 << 1;
+
+/// Error returned by waits cut short by a signal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Interrupted;
+
+
+impl ThreadBlock {
+    /// Signals sent to the thread that have not been handled yet.
+    pub fn pendingSignals(&self) -> u32 {
+        self.pendingSignals.load(Ordering::Acquire)
+    }
+}
+
+/// Send signals to a thread.
+///
+/// If the thread is in an interruptible wait, it is woken
+/// so that it can notice the signals.
+pub fn signalThread(thread: &ThreadBlock, signals: u32) {
+    thread.pendingSignals.fetch_or(signals, Ordering::AcqRel);
+
+    // The thread cannot enter or leave an interruptible
+    // wait while interrupts are disabled.
+    let disabledInterrupts = disableInterrupts();
+
+    if thread.interruptible.get() {
+        let _ = scheduleThread(&disabledInterrupts, &thread.handle());
+    }
+}
+
+/// Whether a wait of the thread should be cut short.
+///
+/// An interrupt only applies to the wait it cuts short,
+/// so it is consumed. A kill is left pending, so that it
+/// cuts short every later wait too until it is acted on.
+pub(super) fn takeInterrupt(thread: &ThreadBlock) -> bool {
+    thread.pendingSignals.fetch_and(!SIGNAL_INTERRUPT, Ordering::AcqRel) != 0
+}
+
+/// Act on the current thread's pending signals.
+///
+/// Called on every return to user mode: by exitKernelMode,
+/// and by the timer and exception handlers when they
+/// interrupted user code.
+/// Does not return if the thread has been killed.
+#[unsafe(no_mangle)]
+pub extern "cdecl" fn checkPendingSignals() {
+    let Some(thread) = getCurrentThread()
+    else { return; };
+
+    let signals = thread.pendingSignals.swap(0, Ordering::AcqRel);
+
+    if signals & SIGNAL_KILL != 0 {
+        exitThread();
+    }
+}
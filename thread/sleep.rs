@@ -0,0 +1,178 @@
+//! Timer wait queue and the sleep system call.
+//!
+//! Threads waiting on the timer are kept in a queue
+//! ordered by deadline, which the timer handler drains
+//! as deadlines pass, rescheduling each expired waiter.
+//! As with cond vars, the queue nodes live on the
+//! waiting threads' stacks, and waking uses the
+//! blockUntil flag protocol so that a wakeup arriving
+//! before the waiter deschedules is not lost.
+
+use core::pin::{Pin, pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::sync::cond::{DO_NOT_DESCHEDULE, TRY_TO_DESCHEDULE};
+use crate::sync::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+use crate::sync::irq_spinlock::{IrqSpinLock, IrqSpinLockGuard};
+use crate::variable_queue::*;
+
+use super::scheduler::{blockUntil, scheduleThread};
+use super::signal::Interrupted;
+use super::timer::get_ticks;
+use super::{ThreadHandle, getCurrentThread};
+
+/// A node in the timer wait queue.
+///
+/// Contains:
+///   link: Queue link to use in variable queue macros.
+///   deadline: Tick at which the waiter should be woken.
+///   thread: The waiting thread.
+///   wake: Flag passed to blockUntil by the waiter, set on expiry.
+///   expired: Whether the deadline was reached.
+#[derive(Debug)]
+pub struct TimerNode {
+    link: Link<TimerNode>,
+    deadline: u32,
+    thread: Option<ThreadHandle>,
+    wake: *const AtomicBool,
+    expired: AtomicBool
+}
+
+unsafe impl Send for TimerNode {}
+
+pub type TimerQueue = Head<TimerNode>;
+
+static timerQueue: IrqSpinLock<TimerQueue> = IrqSpinLock::new(Head::new());
+
+
+/// Error returned by waits whose deadline passed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimedOut;
+
+
+/// Whether a deadline has been reached at the tick now.
+///
+/// Ticks wrap around, so deadlines are compared
+/// by their distance from now.
+#[inline(always)]
+pub fn deadlineReached(deadline: u32, now: u32) -> bool {
+    now.wrapping_sub(deadline) as i32 >= 0
+}
+
+/// The tick a given number of ticks from now.
+#[inline(always)]
+pub fn deadlineAfter(ticks: u32) -> u32 {
+    get_ticks().wrapping_add(ticks)
+}
+
+impl TimerNode {
+    /// Create a timer node for the current thread.
+    ///
+    /// When the deadline passes, wake is set to DO_NOT_DESCHEDULE
+    /// and the thread is rescheduled.
+    /// The node must not outlive wake.
+    pub fn new(deadline: u32, wake: &AtomicBool) -> TimerNode {
+        TimerNode {
+            link: Link::new(),
+            deadline,
+            thread: getCurrentThread().map(|t| t.handle()),
+            wake,
+            expired: AtomicBool::new(false)
+        }
+    }
+
+    /// Whether the deadline was reached.
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Acquire)
+    }
+}
+
+fn getTimerQueue(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<TimerQueue> {
+    timerQueue.lock()
+}
+
+/// Add a node to the timer queue.
+///
+/// The node must be cancelled or expire before it is dropped.
+pub fn addTimer(disabledInterrupts: &DisabledInterruptsGuard, node: Pin<&TimerNode>) {
+    let mut queue = getTimerQueue(disabledInterrupts);
+
+    unsafe {
+        insert_sorted!(&mut queue, node, link, |a: &TimerNode, b: &TimerNode| {
+            (a.deadline.wrapping_sub(b.deadline) as i32) < 0
+        });
+    }
+}
+
+/// Remove a node from the timer queue if it has not expired yet.
+///
+/// Returns whether it had already expired.
+pub fn cancelTimer(disabledInterrupts: &DisabledInterruptsGuard, node: &TimerNode) -> bool {
+    let mut queue = getTimerQueue(disabledInterrupts);
+
+    if node.link.in_queue() {
+        remove!(&mut queue, node, link);
+    }
+
+    node.expired()
+}
+
+/// Wake every waiter whose deadline has passed.
+///
+/// Called by the timer handler on each tick.
+pub(super) fn wakeExpiredTimers(disabledInterrupts: &DisabledInterruptsGuard) {
+    let now = get_ticks();
+    let mut queue = getTimerQueue(disabledInterrupts);
+
+    while let Some(node) = queue.front_ptr() {
+        let node = unsafe { &*node };
+        if !deadlineReached(node.deadline, now) {
+            break;
+        }
+
+        remove!(&mut queue, node, link);
+        node.expired.store(true, Ordering::Release);
+        unsafe { (*node.wake).store(DO_NOT_DESCHEDULE, Ordering::Release); }
+
+        if let Some(thread) = &node.thread {
+            let _ = scheduleThread(disabledInterrupts, thread);
+        }
+    }
+}
+
+
+// Syscalls
+
+
+/// Deschedule the calling thread for a number of ticks.
+///
+/// # Parameters
+/// 1. ticks: Number of timer ticks to sleep for.
+///
+/// # Returns
+///
+/// 0 once the thread has slept,
+/// or immediately if ticks is 0.
+/// -1 if ticks is negative.
+/// -2 if the sleep was interrupted by a signal.
+pub fn sleep(ticks: i32) -> i32 {
+    if ticks < 0 {
+        return -1;
+    } else if ticks == 0 {
+        return 0;
+    }
+
+    let wake = AtomicBool::new(TRY_TO_DESCHEDULE);
+    let node = pin!(TimerNode::new(deadlineAfter(ticks as u32), &wake));
+
+    let disabledInterrupts = disableInterrupts();
+    addTimer(&disabledInterrupts, node.as_ref());
+
+    match blockUntil(&disabledInterrupts, &wake) {
+        Ok(()) => 0,
+        Err(Interrupted) => {
+            cancelTimer(&disabledInterrupts, &node);
+            -2
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Per-thread CPU usage and context switch statistics.
+//!
+//! Counters are only updated by the thread they describe,
+//! or by the timer handler while it is running,
+//! so they need no synchronization beyond Cell.
+//!
+//! Not part of the original C implementation.
+
+use core::cell::Cell;
+
+use super::timer::get_ticks;
+use super::ThreadBlock;
+
+/// Counters describing how a thread has used the CPU.
+///
+/// Contains:
+///   ticksRun: Timer ticks taken while the thread was running.
+///   voluntarySwitches: Times the thread yielded or blocked.
+///   involuntarySwitches: Times the thread was preempted.
+///   blockedTicks: Ticks spent waiting on mutexes and cond vars.
+#[derive(Debug)]
+pub struct ThreadStats {
+    ticksRun: Cell<u32>,
+    voluntarySwitches: Cell<u32>,
+    involuntarySwitches: Cell<u32>,
+    blockedTicks: Cell<u32>
+}
+
+/// A copy of a thread's statistics at one point in time.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ThreadStatsSnapshot {
+    pub tid: i32,
+    pub priority: u8,
+    pub ticksRun: u32,
+    pub voluntarySwitches: u32,
+    pub involuntarySwitches: u32,
+    pub blockedTicks: u32
+}
+
+impl ThreadStats {
+    /// Create a set of zeroed counters.
+    pub const fn new() -> ThreadStats {
+        ThreadStats {
+            ticksRun: Cell::new(0),
+            voluntarySwitches: Cell::new(0),
+            involuntarySwitches: Cell::new(0),
+            blockedTicks: Cell::new(0)
+        }
+    }
+
+    /// Record a timer tick taken while running.
+    pub(super) fn chargeTick(&self) {
+        self.ticksRun.update(|t| t.wrapping_add(1));
+    }
+
+    /// Record a switch away from the thread.
+    pub(super) fn chargeSwitch(&self, voluntary: bool) {
+        let counter = if voluntary {
+            &self.voluntarySwitches
+        } else {
+            &self.involuntarySwitches
+        };
+
+        counter.update(|c| c.wrapping_add(1));
+    }
+}
+
+impl ThreadBlock {
+    /// Take a snapshot of the thread's statistics.
+    pub fn stats(&self) -> ThreadStatsSnapshot {
+        ThreadStatsSnapshot {
+            tid: self.tid(),
+            priority: self.priority(),
+            ticksRun: self.stats.ticksRun.get(),
+            voluntarySwitches: self.stats.voluntarySwitches.get(),
+            involuntarySwitches: self.stats.involuntarySwitches.get(),
+            blockedTicks: self.stats.blockedTicks.get()
+        }
+    }
+
+    /// Record time spent blocked, from the tick since until now.
+    ///
+    /// Called by synchronization primitives once a wait ends.
+    pub fn chargeBlocked(&self, since: u32) {
+        let elapsed = get_ticks().wrapping_sub(since);
+        self.stats.blockedTicks.update(|t| t.wrapping_add(elapsed));
+    }
+}
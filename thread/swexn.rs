@@ -0,0 +1,215 @@
+//! User exception handlers.
+//!
+//! A thread may register a handler and an exception stack
+//! through swexn. When the thread then takes an exception,
+//! the handler is deregistered and invoked on the exception
+//! stack with a ureg_t describing the faulting state,
+//! after which it may swexn back into any valid state.
+//! Exceptions reach deliverException through the
+//! handlers installed by initExceptions.
+
+use core::ffi::c_void;
+use core::ptr::null_mut;
+
+use _410kern::eflags::{
+    EFL_AC, EFL_AF, EFL_CF, EFL_DF, EFL_IF, EFL_OF, EFL_PF, EFL_RESV1, EFL_SF, EFL_TF, EFL_ZF
+};
+use _410kern::seg::{SEGSEL_USER_CS, SEGSEL_USER_DS};
+
+use crate::registers::{ExceptionState, Registers, SuspendedState, Ureg};
+use crate::virtual_memory::{LOGIC_NULL, isUserReadableAddr, isUserWritableAddr};
+
+use super::getCurrentThread;
+
+/// Flags user code may set freely through swexn.
+const USER_EFLAGS: u32 =
+    EFL_CF | EFL_PF | EFL_AF | EFL_ZF | EFL_SF | EFL_TF | EFL_DF | EFL_OF | EFL_AC;
+
+/// Flags that must always be set in user mode.
+///
+/// Every flag outside of USER_EFLAGS and these,
+/// including IOPL, must be clear.
+const REQUIRED_EFLAGS: u32 = EFL_RESV1 | EFL_IF;
+
+/// Frame pushed on the exception stack to call a handler,
+/// as handler(arg, ureg) with a null return address.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct HandlerFrame {
+    returnAddress: u32,
+    arg: u32,
+    ureg: u32
+}
+
+/// Space needed on the exception stack to deliver an exception.
+const EXCEPTION_FRAME_SIZE: usize = size_of::<HandlerFrame>() + size_of::<Ureg>();
+
+
+/// Whether a register state is safe to return to user mode with.
+fn isValidUreg(ureg: &Ureg) -> bool {
+    ureg.cs == SEGSEL_USER_CS as u32
+        && ureg.ss == SEGSEL_USER_DS as u32
+        && ureg.ds == SEGSEL_USER_DS as u32
+        && ureg.es == SEGSEL_USER_DS as u32
+        && ureg.fs == SEGSEL_USER_DS as u32
+        && ureg.gs == SEGSEL_USER_DS as u32
+        && ureg.eflags & !USER_EFLAGS == REQUIRED_EFLAGS
+}
+
+/// Whether the top of an exception stack can hold an exception frame.
+unsafe fn isValidExceptionStack(esp3: *mut c_void) -> bool {
+    let Some(base) = esp3.addr().checked_sub(EXCEPTION_FRAME_SIZE)
+    else { return false; };
+
+    unsafe { isUserWritableAddr(LOGIC_NULL.offset(base), EXCEPTION_FRAME_SIZE) }
+}
+
+/// Set the user state a thread will return to.
+fn adoptUreg(state: &mut SuspendedState, ureg: &Ureg) {
+    state.reg = ureg.reg;
+    state.gs = ureg.gs;
+    state.fs = ureg.fs;
+    state.es = ureg.es;
+    state.ds = ureg.ds;
+    state.eip = ureg.eip;
+    state.cs = ureg.cs;
+    state.eflags = ureg.eflags;
+    state.esp = ureg.esp;
+    state.ss = ureg.ss;
+}
+
+/// Invoke the current thread's exception handler.
+///
+/// Builds a ureg_t and a call frame on the exception stack,
+/// and redirects state to return into the handler.
+/// The handler is deregistered before it runs.
+///
+/// # Parameters
+/// 1. cause: The exception number.
+/// 2. cr2: The faulting address, for page faults.
+/// 3. state: The state saved on entry to the exception handler.
+///
+/// # Returns
+///
+/// true if the exception was handed to a handler,
+/// false if none was registered or its stack is unusable,
+/// in which case the caller should deal with it as usual.
+pub fn deliverException(cause: u32, cr2: u32, state: &mut ExceptionState) -> bool {
+    let Some(thread) = getCurrentThread()
+    else { return false; };
+
+    let handler = thread.swexnHandler.replace(null_mut());
+    let esp3 = thread.esp3.replace(null_mut());
+    let arg = thread.swexnArg.replace(null_mut());
+
+    if handler.is_null() || !unsafe { isValidExceptionStack(esp3) } {
+        return false;
+    }
+
+    let ureg = Ureg {
+        cause,
+        cr2,
+        ds: state.ds,
+        es: state.es,
+        fs: state.fs,
+        gs: state.gs,
+        reg: Registers { esp: 0, ..state.reg },
+        error_code: state.err,
+        eip: state.eip,
+        cs: state.cs,
+        eflags: state.eflags,
+        esp: state.esp,
+        ss: state.ss
+    };
+
+    unsafe {
+        let uregPtr: *mut Ureg = esp3.byte_sub(size_of::<Ureg>()).cast();
+        let framePtr: *mut HandlerFrame = uregPtr.byte_sub(size_of::<HandlerFrame>()).cast();
+
+        uregPtr.write_unaligned(ureg);
+        framePtr.write_unaligned(HandlerFrame {
+            returnAddress: 0,
+            arg: arg.addr() as u32,
+            ureg: uregPtr.addr() as u32
+        });
+
+        thread.exnUreg.set(uregPtr);
+        state.esp = framePtr.addr() as u32;
+    }
+
+    state.eip = handler.addr() as u32;
+
+    true
+}
+
+
+// Syscalls
+
+
+/// Register or deregister a user exception handler,
+/// and optionally adopt a new register state.
+///
+/// Nothing is changed unless every argument is valid.
+///
+/// # Parameters
+/// 1. esp3: Top of the exception stack.
+/// 2. eip: Handler to register.
+///        If either is null, any handler is deregistered instead.
+/// 3. arg: Argument passed to the handler.
+/// 4. newureg: If not null, register state to return to user mode with.
+///
+/// # Returns
+///
+/// 0 on success, with newureg adopted if given.
+/// If newureg was given, the syscall return value
+/// is newureg's eax so that it is not overwritten.
+/// -1 if the handler or exception stack is invalid.
+/// -2 if newureg could not be read or describes an invalid state,
+///    or the thread has no saved user state to replace.
+pub fn swexn(esp3: *mut c_void, eip: *mut c_void, arg: *mut c_void, newureg: *const Ureg) -> i32 {
+    let Some(thread) = getCurrentThread()
+    else { return -1; };
+
+    let register = !esp3.is_null() && !eip.is_null();
+
+    if register && unsafe {
+        !isUserReadableAddr(LOGIC_NULL.offset(eip.addr()), 1) || !isValidExceptionStack(esp3)
+    } {
+        return -1;
+    }
+
+    let newureg = if newureg.is_null() {
+        None
+    } else if thread.suspendedUserState.get().is_null() {
+        return -2;
+    } else if unsafe { isUserReadableAddr(LOGIC_NULL.offset(newureg.addr()), size_of::<Ureg>()) } {
+        let ureg = unsafe { newureg.read_unaligned() };
+
+        if !isValidUreg(&ureg) {
+            return -2;
+        }
+
+        Some(ureg)
+    } else {
+        return -2;
+    };
+
+    if register {
+        thread.swexnHandler.set(eip);
+        thread.esp3.set(esp3);
+        thread.swexnArg.set(arg);
+    } else {
+        thread.swexnHandler.set(null_mut());
+        thread.esp3.set(null_mut());
+        thread.swexnArg.set(null_mut());
+    }
+
+    match newureg {
+        None => 0,
+        Some(ureg) => {
+            let state = thread.suspendedUserState.get();
+            unsafe { adoptUreg(&mut *state, &ureg); }
+            ureg.reg.eax as i32
+        }
+    }
+}
@@ -3,12 +3,20 @@
 use core::cell::Cell;
 use core::ffi::c_void;
 use core::ptr::{self, null_mut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32};
 use crate::registers::*;
 use crate::task::TaskBlock;
 use crate::variable_queue::Link;
 
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::HeldLocks;
+
 use super::thread_internal::*;
 use super::continuation::*;
+use super::scheduler::{DEFAULT_PRIORITY, NO_INHERITED_PRIORITY};
+use super::stats::ThreadStats;
+use super::timer::QUANTUM;
 
 /// Structure to hold initial state of thread
 #[derive(Debug, Copy, Clone)]
@@ -21,28 +29,108 @@ pub struct InitialThreadState {
     state: SuspendedState
 }
 
+/// Structure to hold initial state of a kernel thread
+///
+/// Shaped so that continuing the thread calls entry with arg.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct InitialKernelThreadState {
+    ignoredRegisters: Registers,
+    ignoredEbp: *mut c_void,
+    entry: unsafe extern "cdecl" fn(*mut c_void) -> !,
+    ignoredReturnAddress: *mut c_void,
+    arg: *mut c_void
+}
+
 impl ThreadBlock {
     /// Create a thread block.
     pub(super) fn new() -> ThreadBlock {
         ThreadBlock {
-            tid: 0,
+            tid: TID_NOT_A_THREAD,
             task: null_mut(),
             inKernelDirectory: Cell::new(false),
             kernelStackOffset: Cell::new(KERNEL_STACK_SIZE),
             link: Link::new(),
             free: Cell::new(false),
-            scheduled: Cell::new(false),
-            userDescheduledMutex: null_mut(), //(),
-            userDescheduled: false,
+            exited: Cell::new(false),
+            scheduled: AtomicBool::new(false),
+            userDescheduled: AtomicBool::new(false),
             scheduleLink: Link::new(),
+            priority: Cell::new(DEFAULT_PRIORITY),
+            schedLevel: Cell::new(DEFAULT_PRIORITY),
+            inheritedPriority: Cell::new(NO_INHERITED_PRIORITY),
+            blockedOn: Cell::new(ptr::null()),
+            blockedLink: Link::new(),
+            pass: Cell::new(0),
+            quantumLeft: Cell::new(QUANTUM),
+            stats: ThreadStats::new(),
+            pendingSignals: AtomicU32::new(0),
+            interruptible: Cell::new(false),
             taskLink: Link::new(),
-            suspendedUserState: null_mut(),
-            swexnHandler: null_mut(),
-            esp3: null_mut(),
-            exnUreg: null_mut()
+            tidLink: Link::new(),
+            suspendedUserState: Cell::new(null_mut()),
+            swexnHandler: Cell::new(null_mut()),
+            swexnArg: Cell::new(null_mut()),
+            esp3: Cell::new(null_mut()),
+            exnUreg: Cell::new(null_mut()),
+            refCount: AtomicU32::new(0),
+            weakCount: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+            spinLocksHeld: Cell::new(0),
+            disabledInterruptsRefCount: Cell::new(0),
+            interruptsWereEnabled: Cell::new(false),
+            #[cfg(feature = "lockdep")]
+            heldLocks: HeldLocks::new()
         }
     }
 
+    /// Reset a recycled thread block for a new thread.
+    ///
+    /// Handles to the previous thread may still be cloned,
+    /// dropped or upgraded while this runs, so the counts
+    /// and generation they update are left alone;
+    /// every other field is reset.
+    ///
+    /// Not part of the original C implementation.
+    pub(super) fn recycle(self: Pin<&mut Self>) {
+        let this = unsafe { self.get_unchecked_mut() };
+        let fresh = ThreadBlock::new();
+
+        this.tid = fresh.tid;
+        this.task = fresh.task;
+        this.inKernelDirectory = fresh.inKernelDirectory;
+        this.kernelStackOffset = fresh.kernelStackOffset;
+        this.link = fresh.link;
+        this.free = fresh.free;
+        this.exited = fresh.exited;
+        this.scheduled = fresh.scheduled;
+        this.userDescheduled = fresh.userDescheduled;
+        this.scheduleLink = fresh.scheduleLink;
+        this.priority = fresh.priority;
+        this.schedLevel = fresh.schedLevel;
+        this.inheritedPriority = fresh.inheritedPriority;
+        this.blockedOn = fresh.blockedOn;
+        this.blockedLink = fresh.blockedLink;
+        this.pass = fresh.pass;
+        this.quantumLeft = fresh.quantumLeft;
+        this.stats = fresh.stats;
+        this.pendingSignals = fresh.pendingSignals;
+        this.interruptible = fresh.interruptible;
+        this.taskLink = fresh.taskLink;
+        this.tidLink = fresh.tidLink;
+        this.suspendedUserState = fresh.suspendedUserState;
+        this.swexnHandler = fresh.swexnHandler;
+        this.swexnArg = fresh.swexnArg;
+        this.esp3 = fresh.esp3;
+        this.exnUreg = fresh.exnUreg;
+        this.spinLocksHeld = fresh.spinLocksHeld;
+        this.disabledInterruptsRefCount = fresh.disabledInterruptsRefCount;
+        this.interruptsWereEnabled = fresh.interruptsWereEnabled;
+
+        #[cfg(feature = "lockdep")]
+        { this.heldLocks = fresh.heldLocks; }
+    }
+
     /// Get Tid of a thread block.
     pub fn tid(&self) -> i32 {
         self.tid
@@ -67,4 +155,23 @@ impl ThreadBlock {
             threadState.returnAddress = exitKernelMode;
         }
     }
+
+    /// Load an initial state for a thread that only runs in the kernel.
+    ///
+    /// Once continued, the thread calls entry with arg.
+    /// entry must never return.
+    ///
+    /// Not part of the original C implementation.
+    pub fn loadKernel(&self, entry: unsafe extern "cdecl" fn(*mut c_void) -> !, arg: *mut c_void) {
+        self.kernelStackOffset.set(KERNEL_STACK_SIZE - size_of::<InitialKernelThreadState>());
+
+        unsafe {
+            let threadState: &mut InitialKernelThreadState =
+                &mut *ptr::from_ref(self).cast_mut().byte_add(self.kernelStackOffset.get()).cast();
+
+            threadState.entry = entry;
+            threadState.ignoredReturnAddress = null_mut();
+            threadState.arg = arg;
+        }
+    }
 }
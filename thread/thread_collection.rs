@@ -20,14 +20,41 @@ impl ThreadCollection {
     }
 
     /// Insert a thread into a collection.
+    ///
+    /// The collection takes ownership of the thread allocation.
     pub fn insertThread<'a>(&'a self, thread: Pin<Thread>) -> Pin<&'a ThreadBlock> {
-        let mut guard = self.queue.lockWrite();
-        unsafe { insert_tail!(&mut guard, thread.as_ref(), link) }
+        let mut guard = self.queue.lockWriteUninterruptible();
+        let thread = unsafe { Pin::new_unchecked(&*Pin::into_inner_unchecked(thread).into_raw()) };
+        unsafe { Pin::new_unchecked(insert_tail!(&mut guard, thread, link)) }
     }
 
     /// Remove a thread from a collection.
-    pub fn removeThread<'a>(&self, thread: Pin<&mut ThreadBlock>) -> Pin<Thread> {
-        let mut guard = self.queue.lock();
-        remove!(&mut guard, thread.as_ref(), link);
+    ///
+    /// Ownership of the thread allocation is returned to the caller.
+    pub fn removeThread<'a>(&self, thread: Pin<&ThreadBlock>) -> Pin<Thread> {
+        let mut guard = self.queue.lockWriteUninterruptible();
+        remove!(&mut guard, thread.get_ref(), link);
+        unsafe { Pin::new_unchecked(Thread::from_raw(thread.get_ref())) }
+    }
+
+    /// Remove the first thread from a collection, if any.
+    ///
+    /// Not part of the original C implementation.
+    pub fn popThread(&self) -> Option<Pin<Thread>> {
+        let mut guard = self.queue.lockWriteUninterruptible();
+        let thread = guard.front_ptr()?;
+        remove!(&mut guard, unsafe { &*thread }, link);
+        Some(unsafe { Pin::new_unchecked(Thread::from_raw(thread)) })
+    }
+
+    /// Call a function on each thread in a collection.
+    ///
+    /// The collection cannot be modified while this runs.
+    ///
+    /// Not part of the original C implementation.
+    pub fn forEachThread<F>(&self, mut f: F)
+    where F: FnMut(&ThreadBlock) {
+        let guard = self.queue.lockReadUninterruptible();
+        guard.iter(|t| &t.link).for_each(|t| f(t));
     }
 }
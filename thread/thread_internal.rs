@@ -4,11 +4,16 @@ use core::cell::{Cell, UnsafeCell};
 use core::ffi::c_void;
 use core::pin::Pin;
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicBool, AtomicU32};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32};
 use crate::variable_queue::Link;
 use crate::task::TaskBlock;
 use crate::registers::*;
 
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::HeldLocks;
+
+use super::stats::ThreadStats;
+
 pub(super) const KERNEL_STACK_SIZE: usize = 2048;
 
 pub(super) const TID_NOT_A_THREAD: i32 = -1;
@@ -41,6 +46,11 @@ pub struct ThreadBlock {
     /// Flag for whether the thread is free
     pub(super) free: Cell<bool>,
 
+    /// Flag for whether the thread has exited and is waiting to be reaped.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) exited: Cell<bool>,
+
     /// Flag for whether the thread is scheduled
     pub(super) scheduled: AtomicBool,
 
@@ -50,20 +60,86 @@ pub struct ThreadBlock {
     /// Scheduling Queue link.
     pub(super) scheduleLink: ThreadBlockLink,
 
+    /// Base scheduling priority; lower is more important.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) priority: Cell<u8>,
+
+    /// Priority level whose queue the thread is in while scheduled,
+    /// which aging may raise above its base priority.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) schedLevel: Cell<u8>,
+
+    /// Most important priority lent by threads waiting
+    /// on locks this thread holds,
+    /// or NO_INHERITED_PRIORITY.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) inheritedPriority: Cell<u8>,
+
+    /// Owner slot of the lock this thread is waiting on, if any,
+    /// so that inherited priority can be passed along
+    /// chains of waiting holders.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) blockedOn: Cell<*const AtomicPtr<ThreadBlock>>,
+
+    /// Link in the queue of threads waiting on a lock,
+    /// through which holders find who still lends them priority.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) blockedLink: Link<ThreadBlock>,
+
+    /// Virtual time used by stride scheduling.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) pass: Cell<u64>,
+
+    /// Timer ticks left before the thread is preempted.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) quantumLeft: Cell<u32>,
+
+    /// CPU usage and context switch counters.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) stats: ThreadStats,
+
+    /// Signals sent to the thread that have not been handled yet.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) pendingSignals: AtomicU32,
+
+    /// Whether the thread is in a wait that signals can interrupt.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) interruptible: Cell<bool>,
+
     /// Task's Thread Queue link
     pub(super) taskLink: ThreadBlockLink,
 
+    /// Tid index bucket link.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) tidLink: ThreadBlockLink,
+
     /// Pointer to the saved user state from mode switch.
     pub(super) suspendedUserState: Cell<*mut SuspendedState>,
 
     /// Registered swexn
-    pub(super) swexnHandler: Cell<*mut c_void>, // ...,
+    pub(super) swexnHandler: Cell<*mut c_void>,
+
+    /// Argument for the registered swexn handler
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) swexnArg: Cell<*mut c_void>,
 
     /// Exception stack
-    pub(super) esp3: *mut c_void,
+    pub(super) esp3: Cell<*mut c_void>,
 
     /// Space for ureg_t object on exception stack
-    pub(super) exnUreg: *mut c_void, // ureg_t
+    pub(super) exnUreg: Cell<*mut Ureg>,
 
     /// A count of handles and other references to this object.
     ///
@@ -71,10 +147,45 @@ pub struct ThreadBlock {
     /// after free issues.
     pub(super) refCount: AtomicU32,
 
+    /// A count of weak handles to this block.
+    ///
+    /// The block is not returned to the allocator while any exist,
+    /// so that they can always check its generation.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) weakCount: AtomicU32,
+
+    /// Incremented whenever the thread using this block exits.
+    ///
+    /// Survives the block being recycled, so that weak handles
+    /// to an earlier thread can tell it is gone.
+    ///
+    /// Was not part of the original C implementation.
+    pub(super) generation: AtomicU32,
+
+    /// Locks held by this thread, for lock order validation.
+    ///
+    /// Was not part of the original C implementation.
+    #[cfg(feature = "lockdep")]
+    pub(crate) heldLocks: HeldLocks,
+
+    /// The number of IrqSpinLocks held by this thread,
+    /// which must be zero whenever it is switched away from.
+    ///
+    /// Was not part of the original C implementation.
+    pub(crate) spinLocksHeld: Cell<u32>,
+
     /// The number of active DisabledInterruptsGuards on this thread
     ///
     /// Was not part of the original C implementation.
-    pub(crate) disabledInterruptsRefCount: Cell<u32>
+    pub(crate) disabledInterruptsRefCount: Cell<u32>,
+
+    /// Whether interrupts were enabled before the outermost
+    /// DisabledInterruptsGuard on this thread was created,
+    /// to be restored when it is dropped.
+    ///
+    /// Was not part of the original C implementation.
+    pub(crate) interruptsWereEnabled: Cell<bool>
 }
 
 unsafe impl Send for ThreadBlock {}
@@ -95,4 +206,4 @@ impl ThreadBlock {
 
 
 pub use super::scheduler::getNextThread;
-pub use super::manager::getActiveThreadByTid;
+pub use super::tid_index::getActiveThreadByTid;
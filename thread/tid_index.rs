@@ -0,0 +1,98 @@
+//! Allocation of thread ids, and lookup of threads by tid.
+//!
+//! Not part of the original C implementation, which
+//! walked the list of active threads to find a tid.
+//!
+//! The index is a fixed-size hash table whose buckets
+//! are queues linked through the thread blocks themselves,
+//! so neither assigning nor looking up a tid allocates memory.
+//! Like the schedule, it is only locked with interrupts
+//! disabled, so that it can be used while context switching.
+
+use core::pin::Pin;
+
+use crate::sync::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+use crate::sync::irq_spinlock::{IrqSpinLock, IrqSpinLockGuard};
+use crate::variable_queue::Head;
+
+use super::{ThreadBlock, ThreadHandle, ThreadQueue};
+
+/// Number of buckets in the tid index.
+const TID_BUCKETS: usize = 64;
+
+/// The first tid handed out, and where allocation wraps around to.
+const FIRST_TID: i32 = 1;
+
+/// Holds the tid index, and a lock for synchronization
+struct TidIndex(IrqSpinLock<TidIndexInner>);
+
+struct TidIndexInner {
+    nextTid: i32,
+    buckets: [ThreadQueue; TID_BUCKETS]
+}
+
+static tidIndex: TidIndex = TidIndex::new();
+
+
+impl TidIndex {
+    /// Create an empty tid index.
+    const fn new() -> TidIndex {
+        TidIndex(IrqSpinLock::new(TidIndexInner {
+            nextTid: FIRST_TID,
+            buckets: [const { Head::new() }; TID_BUCKETS]
+        }))
+    }
+}
+
+impl TidIndexInner {
+    /// The bucket a tid hashes to.
+    fn bucket(&mut self, tid: i32) -> &mut ThreadQueue {
+        &mut self.buckets[tid as usize % TID_BUCKETS]
+    }
+
+    /// Find the thread with a given tid.
+    fn find(&mut self, tid: i32) -> Option<&ThreadBlock> {
+        self.bucket(tid).iter(|t| &t.tidLink).find(|t| t.tid == tid)
+    }
+}
+
+fn getTidIndex(_: &DisabledInterruptsGuard) -> IrqSpinLockGuard<TidIndexInner> {
+    tidIndex.0.lock()
+}
+
+/// Assign a new tid to a thread and add it to the index.
+///
+/// Tids increase monotonically, and once they
+/// reach the maximum they wrap around,
+/// skipping over any that are still in use.
+pub(super) fn assignTid(disabledInterrupts: &DisabledInterruptsGuard, mut thread: Pin<&mut ThreadBlock>) -> i32 {
+    let mut index = getTidIndex(disabledInterrupts);
+
+    let tid = loop {
+        let tid = index.nextTid;
+        index.nextTid = if tid == i32::MAX { FIRST_TID } else { tid + 1 };
+
+        if index.find(tid).is_none() {
+            break tid;
+        }
+    };
+
+    unsafe { thread.as_mut().get_unchecked_mut().tid = tid; }
+    unsafe { insert_tail!(index.bucket(tid), thread.into_ref(), tidLink); }
+
+    tid
+}
+
+/// Remove a thread from the index.
+///
+/// Once removed, its tid may be handed out again.
+pub(super) fn removeTid(disabledInterrupts: &DisabledInterruptsGuard, thread: &ThreadBlock) {
+    let mut index = getTidIndex(disabledInterrupts);
+    remove!(index.bucket(thread.tid), thread, tidLink);
+}
+
+/// Obtain an active thread block corresponding to a tid.
+pub fn getActiveThreadByTid(tid: i32) -> Option<ThreadHandle> {
+    let disabledInterrupts = disableInterrupts();
+    getTidIndex(&disabledInterrupts).find(tid).map(|t| t.handle())
+}
@@ -0,0 +1,148 @@
+//! Timer interrupt handling.
+//!
+//! Each timer tick is charged to the running thread.
+//! Once a thread has used up its quantum,
+//! or the scheduling policy would rather run another thread,
+//! the running thread is preempted.
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use _410kern::asm::outb;
+use _410kern::interrupt_defines::{INT_ACK_CURRENT, INT_CTL_PORT};
+use _410kern::seg::{SEGSEL_KERNEL_CS, SEGSEL_USER_CS};
+use _410kern::timer_defines::{
+    TIMER_IDT_ENTRY,
+    TIMER_MODE_IO_PORT,
+    TIMER_PERIOD_IO_PORT,
+    TIMER_RATE,
+    TIMER_SQUARE_WAVE
+};
+
+use crate::byte_utils::{LSB, MSB};
+use crate::idt_entry::{HARDWARE_PRIVILEGE, IDT, INTERRUPT_GATE};
+use crate::registers::SuspendedState;
+use crate::sync::disable_interrupts::{DisabledInterruptsGuard, disableInterrupts};
+
+use super::context_switch::preemptThread;
+use super::idle::{chargeIdleTick, isIdleThread};
+use super::scheduler::tickSchedule;
+use super::signal::checkPendingSignals;
+use super::sleep::wakeExpiredTimers;
+use super::getCurrentThread;
+
+/// Number of timer interrupts per second.
+pub const TICKS_PER_SECOND: u32 = 100;
+
+/// Number of ticks a thread may run before it is preempted.
+pub const QUANTUM: u32 = 2;
+
+/// Number of ticks since the timer was started.
+static ticks: AtomicU32 = AtomicU32::new(0);
+
+
+/// Program the timer and install its interrupt handler.
+pub fn initTimer() {
+    let period = (TIMER_RATE / TICKS_PER_SECOND) as u16;
+
+    unsafe {
+        *IDT.add(TIMER_IDT_ENTRY) =
+            INTERRUPT_GATE(timerInterruptWrapper as u32, SEGSEL_KERNEL_CS, HARDWARE_PRIVILEGE);
+
+        outb(TIMER_MODE_IO_PORT, TIMER_SQUARE_WAVE);
+        outb(TIMER_PERIOD_IO_PORT, LSB(period));
+        outb(TIMER_PERIOD_IO_PORT, MSB(period));
+    }
+}
+
+/// Saves the interrupted state in the shape of a SuspendedState,
+/// and calls the timer handler with it.
+#[unsafe(naked)]
+unsafe extern "cdecl" fn timerInterruptWrapper() {
+    naked_asm!(
+        "push %ds",
+        "push %es",
+        "push %fs",
+        "push %gs",
+        "pusha",
+        "push %esp",
+        "call {handler}",
+        "add $4, %esp",
+        "popa",
+        "pop %gs",
+        "pop %fs",
+        "pop %es",
+        "pop %ds",
+        "iret",
+        handler = sym timerInterruptHandler,
+        options(att_syntax)
+    );
+}
+
+/// Handle a timer interrupt.
+///
+/// If the interrupt was taken in user mode, pending signals
+/// are acted on before returning to it, so a thread spinning
+/// in user mode can still be killed.
+///
+/// Runs with interrupts disabled, as it is entered through an interrupt gate.
+extern "cdecl" fn timerInterruptHandler(state: &SuspendedState) {
+    ticks.fetch_add(1, Ordering::AcqRel);
+
+    // Acknowledge the interrupt first, as we may not
+    // return here until this thread runs again.
+    unsafe { outb(INT_CTL_PORT, INT_ACK_CURRENT); }
+
+    let disabledInterrupts = disableInterrupts();
+    wakeExpiredTimers(&disabledInterrupts);
+
+    if chargeQuantum(&disabledInterrupts) {
+        let _ = preemptThread(&disabledInterrupts);
+    }
+
+    drop(disabledInterrupts);
+
+    if state.cs == SEGSEL_USER_CS as u32 {
+        checkPendingSignals();
+    }
+}
+
+/// Charge the running thread for a tick.
+///
+/// Returns whether it should be preempted,
+/// in which case it is given a new quantum.
+fn chargeQuantum(disabledInterrupts: &DisabledInterruptsGuard) -> bool {
+    let Some(thread) = getCurrentThread()
+    else { return false; };
+
+    thread.stats.chargeTick();
+
+    // The idle thread is not in the schedule, and should
+    // give way as soon as anything else is runnable.
+    if isIdleThread(thread) {
+        chargeIdleTick();
+        return true;
+    }
+
+    let left = thread.quantumLeft.get().saturating_sub(1);
+    let preferred = tickSchedule(disabledInterrupts, thread);
+
+    if left == 0 || preferred {
+        thread.quantumLeft.set(QUANTUM);
+        true
+    } else {
+        thread.quantumLeft.set(left);
+        false
+    }
+}
+
+
+// Syscalls
+
+
+/// Get the number of timer ticks since boot.
+///
+/// The count wraps around once it overflows.
+pub fn get_ticks() -> u32 {
+    ticks.load(Ordering::Acquire)
+}
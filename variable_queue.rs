@@ -16,9 +16,9 @@ unsafe impl<Elem: Send> Send for Head<Elem> {}
 ///  collected into a queue owned by Head<Elem>
 #[derive(Debug)]
 pub struct Link<Elem> {
-    next: *const Elem,
-    prev: *const Elem,
-    inQueue: bool,
+    next: Cell<*const Elem>,
+    prev: Cell<*const Elem>,
+    inQueue: Cell<bool>,
     phantomPinned: PhantomPinned
 }
 
@@ -200,6 +200,18 @@ impl<Elem> Head<Elem> {
         toinsert
     }
 
+    pub unsafe fn insert_sorted<'a, F, C>(&mut self, elem: Pin<&'a Elem>, link_name: F, precedes: C) -> &'a Elem
+    where F: Fn(&Elem) -> &Link<Elem>, C: Fn(&Elem, &Elem) -> bool {
+        let next = self.iter(&link_name)
+            .find(|curr| precedes(elem.get_ref(), curr))
+            .map(|curr| curr as *const Elem);
+
+        match next {
+            None => self.insert_tail(elem, link_name),
+            Some(next) => self.insert_before(&*next, elem, link_name)
+        }
+    }
+
     pub fn remove<F>(&mut self, elem: &Elem, link_name: F)
     where F: Fn(&Elem) -> &Link<Elem> {
         if link_name(elem).in_queue() {
@@ -315,6 +327,22 @@ macro_rules! insert_before {
     }}
 }
 
+/// Inserts the queue element toinsert before the first element
+/// it precedes in the queue.
+///
+/// If the queue is sorted according to precedes, which takes
+/// two elements and returns whether the first belongs before the second,
+/// it remains sorted. Elements that compare equal keep the order
+/// in which they were inserted.
+///
+/// This macro is unsafe and must be used in an unsafe block;
+/// the caller must guarantee $toinsert remains pinned while in the queue.
+macro_rules! insert_sorted {
+    ( $head:expr, $toinsert:expr, $link_name:ident, $precedes:expr ) => {{
+        ($head).insert_sorted($toinsert, |e| &e.$link_name, $precedes)
+    }}
+}
+
  /// Detaches the element elem from the queue organized by link_name.
  ///
  /// If head does not use the link named link_name to organize its elements or
@@ -244,7 +244,7 @@ pub use direct_mapping::DirectMapping;
 
 
 /* Page Directories */
-pub use manager::kernelDirectory;
+pub use manager::{kernelDirectory, useKernelDirectory};
 
 
 /* Memory Allocation and Freeing */
@@ -269,3 +269,9 @@ pub use validate_memory::{
     isUnmappedAddr,
     readableStringLen
 };
+
+
+/* Page Faults */
+
+pub use page_fault::resolvePageFault;
+pub use common_kern::USER_MEM_START;
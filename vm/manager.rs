@@ -1,32 +1,52 @@
 //! Functions for mapping virtual memory.
 
-use core::cell::OnceCell;
-use core::mem::MaybeUninit;
-use core::ptr::null_mut;
+use core::ptr;
 
+use _410kern::cr::{get_cr0, set_cr0, set_cr3};
 use _410kern::page::PAGE_SIZE;
 use alloc::boxed::Box;
 
-use crate::sync::mutex::Mutex;
+use crate::sync::once::{Lazy, Once};
 use crate::virtual_memory::*;
 
 use super::common_kern::machine_phys_frames;
 use super::vm_internal::{PageTable, mapPage};
 use super::frame_alloc::allocFrame;
 
-static mut _kernelDirectory: *const PageDirectory = null_mut();
-static mut _zeroedPage: *const Page = null_mut();
+/// Makes writes from the kernel fault on read-only pages,
+/// so that they go through copy-on-write like user writes.
+const CR0_WP: u32 = 1 << 16;
+
+/// The kernel page directory, set once by initVirtualMemory.
+static _kernelDirectory: Once<&'static PageDirectory> = Once::new();
+
+/// A page of zeroes, allocated on first use.
+static _zeroedPage: Lazy<&'static Page> = Lazy::new(|| {
+    let zeroedPage = unsafe { &mut *assume_direct_mapping::<Page>(allocFrame().unwrap()) };
+    zeroedPage.zero();
+    zeroedPage
+});
 
 /// Return the kernel page directory
+///
+/// Null until initVirtualMemory has run.
 #[inline(always)]
 pub fn kernelDirectory() -> *const PageDirectory {
-    unsafe { _kernelDirectory }
+    _kernelDirectory.get().map_or(ptr::null(), |dir| ptr::from_ref(*dir))
+}
+
+/// Switch to the kernel page directory.
+///
+/// Not part of the original C implementation.
+#[inline(always)]
+pub unsafe fn useKernelDirectory() {
+    unsafe { set_cr3(from_direct_mapping(kernelDirectory().cast_mut())); }
 }
 
 /// Return a zeroed page
 #[inline(always)]
 pub fn zeroedPage() -> &'static Page {
-    unsafe { &*_zeroedPage.get().unwrap() }
+    *_zeroedPage
 }
 
 /// Return address of start of the next page to the input address
@@ -68,17 +88,13 @@ pub unsafe fn initVirtualMemory() {
         }
     }
 
-    unsafe {
-        *_kernelDirectory = MaybeUninit::new(Mutex::new(kernelDirectory));
+    if _kernelDirectory.set(Box::leak(kernelDirectory)).is_err() {
+        panic!("initVirtualMemory called twice");
     }
 
-    _kernelDirectory.set(kernelDirectory).unwrap();
-
-    todo!();
+    unsafe { set_cr0(get_cr0() | CR0_WP); }
 
-    unsafe {
-        let zeroedPage = unsafe { assume_direct_mapping::<Page>(allocFrame().unwrap()) };
-        (&mut *zeroedPage).zero();
-        _zeroedPage = zeroedPage;
-    }
+    // Allocate the zeroed page now rather than
+    // from whichever thread first needs it.
+    Lazy::force(&_zeroedPage);
 }
@@ -11,6 +11,7 @@ pub(super) mod manager;
 pub(super) mod mapped_memory;
 pub(super) mod memory_alloc;
 pub(super) mod validate_memory;
+pub(super) mod page_fault;
 mod frame_alloc;
 mod invalidate_page;
 
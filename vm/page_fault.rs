@@ -0,0 +1,53 @@
+//! Resolves page faults that are part of normal operation.
+//!
+//! Not part of the original C implementation.
+
+use _410kern::cr::get_cr3;
+
+use crate::virtual_memory::*;
+
+use super::frame_alloc::allocFrame;
+use super::vm_internal::invalidatePage;
+
+/// Set in a page fault's error code if the page was present.
+const PF_PRESENT: u32 = 1 << 0;
+
+/// Set in a page fault's error code if the access was a write.
+const PF_WRITE: u32 = 1 << 1;
+
+/// Resolve a page fault in the current directory.
+///
+/// A write to a copy-on-write page gives it a copy
+/// of its own, which is then writable.
+/// The old frame may still be shared, so it is left alone.
+///
+/// Returns true if the faulting access can be retried,
+/// false if the fault is a genuine error.
+pub unsafe fn resolvePageFault(addr: LogicalAddress, errorCode: u32) -> bool {
+    if errorCode & PF_PRESENT == 0 || errorCode & PF_WRITE == 0 {
+        return false;
+    }
+
+    let Some(dir) = (unsafe { assume_direct_mapping::<PageDirectory>(get_cr3()).as_mut() })
+    else { return false; };
+
+    let Some(entry) = (unsafe { dir.tryGetPageEntryMut(addr) })
+    else { return false; };
+
+    if !entry.page_is_present() || !entry.page_is_copy_on_write() {
+        return false;
+    }
+
+    let Some(frame) = allocFrame()
+    else { return false; };
+
+    unsafe {
+        let old = &*assume_direct_mapping::<Page>(entry.page_address());
+        old.copyPage(&mut *assume_direct_mapping::<Page>(frame));
+    }
+
+    *entry = PageEntry(frame | (entry.page_flags() as u32 & !PAGE_COPY_ON_WRITE) | PAGE_WRITABLE);
+    invalidatePage(addr);
+
+    true
+}
@@ -46,6 +46,9 @@ pub unsafe fn isUserReadableAddr(addr: LogicalAddress, len: usize) -> bool {
 }
 
 /// Checks if a sequence of addresses is user-writable.
+///
+/// Copy-on-write pages count, as a write
+/// to them is resolved by resolvePageFault.
 #[inline(always)]
 pub unsafe fn isUserWritableAddr(addr: LogicalAddress, len: usize) -> bool {
     foreach_page_in(addr, addr.offset(len)).all(|curr| {
@@ -53,7 +56,7 @@ pub unsafe fn isUserWritableAddr(addr: LogicalAddress, len: usize) -> bool {
             None => false,
             Some(entry) => entry.page_is_present()
                 && GET_BIT(entry, PAGE_USER_ACCESS_BIT)
-                && GET_BIT(entry, PAGE_WRITABLE_BIT)
+                && (GET_BIT(entry, PAGE_WRITABLE_BIT) || entry.page_is_copy_on_write())
         }
     })
 }